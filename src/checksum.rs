@@ -0,0 +1,111 @@
+//! Fixup section, loader section and per-page checksums, and checking them.
+//!
+//! The LE/LX specs leave the algorithm to the linker. Here every checksum is
+//! the 32-bit wrapping sum of the data as little-endian dwords, the last one
+//! padded with zeros. It covers:
+//!
+//! - fixup section (34h): the fixup page table up to the end of the import
+//!   procedure name table, the 30h bytes from the fixup page table offset
+//! - loader section (3Ch): the object table up to the fixup page table, the
+//!   38h bytes from the object table offset, per-page checksums included
+//! - each page (table at 7Ch, one dword per page map entry): the bytes
+//!   stored in the file for it, 0 for pages that aren't stored
+
+use crate::error::{self, Error};
+
+pub fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut dword = [0u8; 4];
+        dword[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_le_bytes(dword))
+    })
+}
+
+/// One checksum of a file, as written and as worked out from its contents.
+pub struct Check {
+    pub what: String,
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl Check {
+    pub fn ok(&self) -> bool { self.stored == self.computed }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> error::Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .ok_or(Error::Truncated { offset: offset as u64, len: 4 })
+}
+
+fn slice(data: &[u8], offset: usize, len: usize) -> error::Result<&[u8]> {
+    data.get(offset..offset + len)
+        .ok_or(Error::Truncated { offset: offset as u64, len: len as u64 })
+}
+
+/// The bytes stored in the file for each page map entry of an LE or LX
+/// header at `header`.
+fn stored_pages(file: &[u8], header: usize, lx: bool) -> error::Result<Vec<&[u8]>> {
+    let num_pages = read_u32(file, header + 0x14)? as usize;
+    let page_size = read_u32(file, header + 0x28)? as usize;
+    let page_map = header + read_u32(file, header + 0x48)? as usize;
+    let data_pages = read_u32(file, header + 0x80)? as usize;
+    let mut pages = Vec::new();
+    if lx {
+        let shift = read_u32(file, header + 0x2C)?;
+        for page in 0..num_pages {
+            let entry = slice(file, page_map + page * 8, 8)?;
+            let offset = (u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]) << shift) as usize;
+            let size = u16::from_le_bytes([entry[4], entry[5]]) as usize;
+            pages.push(slice(file, data_pages + offset, size)?);
+        }
+    } else {
+        let last_page_bytes = read_u32(file, header + 0x2C)? as usize;
+        let entries = slice(file, page_map, num_pages * 4)?;
+        let numbers: Vec<usize> = entries.chunks(4)
+            .map(|entry| (entry[0] as usize) << 16 | (entry[1] as usize) << 8 | entry[2] as usize)
+            .collect();
+        let last_number = numbers.iter().copied().max().unwrap_or(0);
+        for number in numbers {
+            if number == 0 { pages.push(&[][..]); continue }
+            let size = if number == last_number { last_page_bytes } else { page_size };
+            pages.push(slice(file, data_pages + (number - 1) * page_size, size)?);
+        }
+    }
+    Ok(pages)
+}
+
+/// Checks the checksums of an LE or LX executable (or bare header) against
+/// its contents. Checksums that are 0 weren't written and aren't checked,
+/// so no checks at all means there were none.
+pub fn verify(file: &[u8]) -> error::Result<Vec<Check>> {
+    // Behind an MZ stub, or on its own
+    let header = if file.starts_with(b"MZ") { read_u32(file, 0x3C)? as usize } else { 0 };
+    let lx = match file.get(header..header + 2) {
+        Some(b"LX") => true,
+        Some(b"LE") => false,
+        _ => return Err(Error::NotLe { offset: header as u64 })
+    };
+    let mut checks = Vec::new();
+    let fixup_checksum = read_u32(file, header + 0x34)?;
+    if fixup_checksum != 0 {
+        let fixup_section = slice(file, header + read_u32(file, header + 0x68)? as usize, read_u32(file, header + 0x30)? as usize)?;
+        checks.push(Check { what: String::from("fixup section"), stored: fixup_checksum, computed: checksum(fixup_section) });
+    }
+    let loader_checksum = read_u32(file, header + 0x3C)?;
+    if loader_checksum != 0 {
+        let loader_section = slice(file, header + read_u32(file, header + 0x40)? as usize, read_u32(file, header + 0x38)? as usize)?;
+        checks.push(Check { what: String::from("loader section"), stored: loader_checksum, computed: checksum(loader_section) });
+    }
+    let page_checksums = read_u32(file, header + 0x7C)? as usize;
+    if page_checksums != 0 {
+        for (idx, page) in stored_pages(file, header, lx)?.iter().enumerate() {
+            checks.push(Check {
+                what: format!("page {}", idx + 1),
+                stored: read_u32(file, header + page_checksums + idx * 4)?,
+                computed: checksum(page),
+            });
+        }
+    }
+    Ok(checks)
+}
@@ -0,0 +1,293 @@
+//! Reading the input's debug sections back against the LE layout.
+//!
+//! The input is a relocatable object, so addresses in `.debug_*` are only
+//! meaningful once their relocations are applied. Here they are applied
+//! against where each input section ended up in the LE objects.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use object::{Object, ObjectSection, ObjectSymbol, RelocationKind, RelocationTarget, SectionIndex};
+
+/// Input section -> (LE object number, offset inside that object).
+pub type Placements = HashMap<SectionIndex, (u8, u64)>;
+
+/// A function or data symbol of the merged object.
+pub struct DebugSymbol {
+    pub name: String,
+    pub object: u8,
+    pub offset: u64,
+    pub size: u64,
+    pub code: bool,
+    pub global: bool,
+}
+
+/// One compilation unit, with its line table in code object offsets.
+pub struct DebugModule {
+    pub name: String,
+    /// (offset in code object, line number), sorted by offset
+    pub lines: Vec<(u32, u32)>,
+}
+
+/// Returns a copy of `section`'s data with its relocations applied, as if it
+/// was loaded at `section_base`.
+///
+/// Targets that were placed in an LE object resolve to `bases[object - 1]`
+/// plus their offset in it; targets in other debug sections resolve to their
+/// section offset, which is what DWARF wants for `DW_FORM_strp`,
+/// `DW_AT_stmt_list` and friends.
+pub fn relocate_section(obj_file: &object::File, section: &object::Section, placements: &Placements, bases: &[u64], section_base: u64) -> Vec<u8> {
+    let mut data = section.data().unwrap_or(&[]).to_vec();
+    for (offset, rel) in section.relocations() {
+        let pc_relative = match rel.kind() {
+            RelocationKind::Absolute => false,
+            RelocationKind::Relative | RelocationKind::PltRelative => true,
+            _ => continue
+        };
+        let (target_sec, target_off) = match rel.target() {
+            RelocationTarget::Symbol(idx) => match obj_file.symbol_by_index(idx) {
+                Ok(sym) => (sym.section_index(), sym.address()),
+                Err(_) => continue
+            },
+            RelocationTarget::Section(idx) => (Some(idx), 0),
+            _ => continue
+        };
+        let base = match target_sec {
+            Some(idx) => match placements.get(&idx) {
+                Some((object, placed)) => bases.get(*object as usize - 1).unwrap_or(&0) + placed,
+                None => 0
+            },
+            None => 0
+        };
+        let start = offset as usize;
+        let width = rel.size() as usize / 8;
+        if start + width > data.len() { continue }
+        let addend = if rel.has_implicit_addend() {
+            let mut arr: [u8; 8] = [0; 8];
+            arr[..width].copy_from_slice(&data[start..start + width]);
+            i64::from_le_bytes(arr)
+        } else {
+            rel.addend()
+        };
+        let mut value = (base + target_off).wrapping_add(addend as u64);
+        if pc_relative {
+            value = value.wrapping_sub(section_base + offset);
+        }
+        data[start..start + width].copy_from_slice(&value.to_le_bytes()[..width]);
+    }
+    data
+}
+
+/// Walks the DWARF line programs of the input and returns one module per
+/// compilation unit, with the lines in `code_object` (numbered from 1).
+/// Only rows from the unit's own source file are kept, as the consumers here
+/// have no notion of included files.
+pub fn read_dwarf_modules(obj_file: &object::File, placements: &Placements, code_object: u8, object_sizes: &[u32]) -> std::result::Result<Vec<DebugModule>, Box<dyn std::error::Error>> {
+    // Objects one after another, so addresses tell them apart
+    let bases: Vec<u64> = object_sizes.iter()
+        .scan(0u64, |next, size| {
+            let base = *next;
+            *next += *size as u64;
+            Some(base)
+        })
+        .collect();
+    let code_base = bases.get(code_object as usize - 1).copied().unwrap_or(0);
+    let code_size = object_sizes.get(code_object as usize - 1).copied().unwrap_or(0) as u64;
+    let load_section = |id: gimli::SectionId| -> std::result::Result<Cow<[u8]>, gimli::Error> {
+        Ok(match obj_file.section_by_name(id.name()) {
+            Some(section) => Cow::Owned(relocate_section(obj_file, &section, placements, &bases, 0)),
+            None => Cow::Borrowed(&[][..])
+        })
+    };
+    let dwarf_cow = gimli::Dwarf::load(load_section)?;
+    let dwarf = dwarf_cow.borrow(|section| gimli::EndianSlice::new(section, gimli::LittleEndian));
+
+    let mut modules = Vec::new();
+    let mut units = dwarf.units();
+    while let Some(header) = units.next()? {
+        let unit = dwarf.unit(header)?;
+        let name = match &unit.name {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => continue
+        };
+        let base_name = name.rsplit('/').next().unwrap_or(&name).to_string();
+        let mut lines = Vec::new();
+        if let Some(program) = unit.line_program.clone() {
+            let mut rows = program.rows();
+            while let Some((header, row)) = rows.next_row()? {
+                if row.end_sequence() { continue }
+                let line = match row.line() {
+                    Some(line) => line.get() as u32,
+                    None => continue
+                };
+                let file_name = match row.file(header) {
+                    Some(file) => dwarf.attr_string(&unit, file.path_name())?.to_string_lossy().into_owned(),
+                    None => continue
+                };
+                if file_name.rsplit('/').next() != Some(base_name.as_str()) { continue }
+                let offset = match row.address().checked_sub(code_base) {
+                    Some(offset) if offset < code_size => offset as u32,
+                    _ => continue
+                };
+                lines.push((offset, line));
+            }
+        }
+        lines.sort();
+        lines.dedup_by_key(|(offset, _)| *offset);
+        modules.push(DebugModule { name, lines });
+    }
+    Ok(modules)
+}
+
+/// An LE object as it appears in the debug ELF.
+pub struct DebugObject<'a> {
+    pub name: &'a str,
+    pub base: u64,
+    pub size: u64,
+    pub code: bool,
+    /// Object contents, already relocated to `base`. Without them the
+    /// section is NOBITS and no program header is written for it.
+    pub data: Option<Vec<u8>>,
+}
+
+/// Builds an i386 ELF executable describing the LE image: one section per LE
+/// object at its base address, every `.debug_*` section of the input with
+/// relocations applied against those addresses, and the merged symbols.
+///
+/// Objects without contents only give the debug info and symbols something
+/// to point into, which is all addr2line needs. With contents each object
+/// also gets a PT_LOAD so gdb sees a properly linked program. `entry` is
+/// the address the program starts at, its CS object's base plus EIP.
+pub fn build_debug_elf(obj_file: &object::File, placements: &Placements, objects: &[DebugObject], symbols: &[DebugSymbol], entry: u64, verbose: bool) -> std::result::Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let bases: Vec<u64> = objects.iter().map(|obj| obj.base).collect();
+    let mut debug_sections = Vec::new();
+    for section in obj_file.sections() {
+        let name = section.name()?;
+        if !name.starts_with(".debug_") { continue }
+        let data = relocate_section(obj_file, &section, placements, &bases, 0);
+        if verbose { println!("{} 0x{:x} bytes", name, data.len()); }
+        debug_sections.push((String::from(name), data));
+    }
+    // ELF wants local symbols before global ones
+    let mut ordered_symbols: Vec<&DebugSymbol> = symbols.iter().collect();
+    ordered_symbols.sort_by_key(|sym| sym.global);
+    let num_local = ordered_symbols.iter().filter(|sym| !sym.global).count() as u32 + 1;
+
+    let mut buffer = Vec::new();
+    let mut writer = object::write::elf::Writer::new(object::Endianness::Little, false, &mut buffer);
+    writer.reserve_file_header();
+    let num_loaded = objects.iter().filter(|obj| obj.data.is_some()).count() as u32;
+    writer.reserve_program_headers(num_loaded);
+
+    writer.reserve_null_section_index();
+    let object_sections: Vec<_> = objects.iter()
+        .map(|obj| (writer.add_section_name(obj.name.as_bytes()), writer.reserve_section_index()))
+        .collect();
+    let debug_indices: Vec<_> = debug_sections.iter()
+        .map(|(name, _)| (writer.add_section_name(name.as_bytes()), writer.reserve_section_index()))
+        .collect();
+
+    let symbol_names: Vec<_> = ordered_symbols.iter().map(|sym| writer.add_string(sym.name.as_bytes())).collect();
+    writer.reserve_null_symbol_index();
+    for sym in ordered_symbols.iter() {
+        writer.reserve_symbol_index(Some(object_sections[sym.object as usize - 1].1));
+    }
+    writer.reserve_symtab_section_index();
+    writer.reserve_strtab_section_index();
+    writer.reserve_shstrtab_section_index();
+
+    let object_offsets: Vec<_> = objects.iter().map(|obj| match &obj.data {
+        Some(data) => writer.reserve(data.len(), 0x1000),
+        None => 0
+    }).collect();
+    let debug_offsets: Vec<_> = debug_sections.iter().map(|(_, data)| writer.reserve(data.len(), 1)).collect();
+    writer.reserve_symtab();
+    writer.reserve_strtab();
+    writer.reserve_shstrtab();
+    writer.reserve_section_headers();
+
+    writer.write_file_header(&object::write::elf::FileHeader {
+        os_abi: object::elf::ELFOSABI_NONE,
+        abi_version: 0,
+        e_type: object::elf::ET_EXEC,
+        e_machine: object::elf::EM_386,
+        e_entry: entry,
+        e_flags: 0,
+    })?;
+    writer.write_align_program_headers();
+    for (obj, offset) in objects.iter().zip(object_offsets.iter()) {
+        if let Some(data) = &obj.data {
+            writer.write_program_header(&object::write::elf::ProgramHeader {
+                p_type: object::elf::PT_LOAD,
+                p_flags: object::elf::PF_R | if obj.code { object::elf::PF_X } else { object::elf::PF_W },
+                p_offset: *offset as u64,
+                p_vaddr: obj.base,
+                p_paddr: obj.base,
+                p_filesz: data.len() as u64,
+                p_memsz: obj.size,
+                p_align: 1,
+            });
+        }
+    }
+    for (obj, offset) in objects.iter().zip(object_offsets.iter()) {
+        if let Some(data) = &obj.data {
+            writer.pad_until(*offset);
+            writer.write(data);
+        }
+    }
+    for (_, data) in debug_sections.iter() {
+        writer.write(data);
+    }
+
+    writer.write_null_symbol();
+    for (sym, name) in ordered_symbols.iter().zip(symbol_names) {
+        let bind = if sym.global { object::elf::STB_GLOBAL } else { object::elf::STB_LOCAL };
+        let kind = if sym.code { object::elf::STT_FUNC } else { object::elf::STT_OBJECT };
+        writer.write_symbol(&object::write::elf::Sym {
+            name: Some(name),
+            section: Some(object_sections[sym.object as usize - 1].1),
+            st_info: (bind << 4) | kind,
+            st_other: object::elf::STV_DEFAULT,
+            st_shndx: 0,
+            st_value: bases[sym.object as usize - 1] + sym.offset,
+            st_size: sym.size,
+        });
+    }
+    writer.write_strtab();
+    writer.write_shstrtab();
+
+    writer.write_null_section_header();
+    for ((obj, (name, _)), offset) in objects.iter().zip(object_sections.iter()).zip(object_offsets) {
+        writer.write_section_header(&object::write::elf::SectionHeader {
+            name: Some(*name),
+            sh_type: if obj.data.is_some() { object::elf::SHT_PROGBITS } else { object::elf::SHT_NOBITS },
+            sh_flags: (object::elf::SHF_ALLOC | if obj.code { object::elf::SHF_EXECINSTR } else { object::elf::SHF_WRITE }) as u64,
+            sh_addr: obj.base,
+            sh_offset: offset as u64,
+            sh_size: obj.size,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 1,
+            sh_entsize: 0,
+        });
+    }
+    for ((name, _), ((_, data), offset)) in debug_indices.iter().zip(debug_sections.iter().zip(debug_offsets)) {
+        writer.write_section_header(&object::write::elf::SectionHeader {
+            name: Some(*name),
+            sh_type: object::elf::SHT_PROGBITS,
+            sh_flags: 0,
+            sh_addr: 0,
+            sh_offset: offset as u64,
+            sh_size: data.len() as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 1,
+            sh_entsize: 0,
+        });
+    }
+    writer.write_symtab_section_header(num_local);
+    writer.write_strtab_section_header();
+    writer.write_shstrtab_section_header();
+
+    Ok(buffer)
+}
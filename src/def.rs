@@ -0,0 +1,166 @@
+//! Export and import lists, from the command line or a .def-style file.
+//!
+//! Only the EXPORTS and IMPORTS parts of a .def file are read, one entry per
+//! line:
+//!
+//!   EXPORTS
+//!       name[=internal] [@ordinal] [NONAME]
+//!   IMPORTS
+//!       [internal=]module.name
+//!       internal=module.ordinal
+//!
+//! `internal` is the symbol in the input, if it isn't called `name`. Other
+//! statements (LIBRARY, DESCRIPTION, ...) are skipped and `;` starts a
+//! comment.
+
+pub struct Export {
+    /// Name in the names tables
+    pub name: String,
+    /// Symbol it refers to
+    pub symbol: String,
+    pub ordinal: Option<u16>,
+    /// Leave it out of the names tables, import by ordinal only
+    pub noname: bool,
+}
+
+pub enum ImportEntry {
+    Name(String),
+    Ordinal(u16),
+}
+
+pub struct Import {
+    /// Undefined symbol of the input it resolves
+    pub symbol: String,
+    pub module: String,
+    pub entry: ImportEntry,
+}
+
+#[derive(Default)]
+pub struct Definitions {
+    pub exports: Vec<Export>,
+    pub imports: Vec<Import>,
+}
+
+const STATEMENTS: &[&str] = &[
+    "CODE", "DATA", "DESCRIPTION", "EXETYPE", "EXPORTS", "HEAPSIZE", "IMPORTS",
+    "LIBRARY", "NAME", "PROTMODE", "SEGMENTS", "STACKSIZE", "STUB", "VERSION",
+];
+
+fn parse_ordinal(s: &str) -> Result<u16, String> {
+    match s.parse() {
+        Ok(ordinal) if ordinal != 0 => Ok(ordinal),
+        _ => Err(format!("bad ordinal '{}', must be 1 to 65535", s))
+    }
+}
+
+fn parse_name(s: &str) -> Result<(String, String), String> {
+    let (name, symbol) = s.split_once('=').unwrap_or((s, s));
+    if name.is_empty() || symbol.is_empty() {
+        return Err(format!("bad export '{}'", s));
+    }
+    if name.len() > 0xFF {
+        return Err(format!("export name '{}' is longer than 255 bytes", name));
+    }
+    Ok((String::from(name), String::from(symbol)))
+}
+
+/// Parses a command line export, `name[=internal][@ordinal]`.
+pub fn parse_export(spec: &str) -> Result<Export, String> {
+    let (name, ordinal) = match spec.rsplit_once('@') {
+        Some((name, ordinal)) => (name, Some(parse_ordinal(ordinal)?)),
+        None => (spec, None)
+    };
+    let (name, symbol) = parse_name(name)?;
+    Ok(Export { name, symbol, ordinal, noname: false })
+}
+
+/// Parses an import, `[internal=]module.name` or `internal=module.ordinal`.
+pub fn parse_import(spec: &str) -> Result<Import, String> {
+    let (symbol, target) = match spec.split_once('=') {
+        Some((symbol, target)) => (Some(symbol), target),
+        None => (None, spec)
+    };
+    let (module, entry) = target.rsplit_once('.')
+        .filter(|(module, entry)| !module.is_empty() && !entry.is_empty())
+        .ok_or_else(|| format!("bad import '{}', should be [internal=]module.name", spec))?;
+    if module.len() > 0xFF || entry.len() > 0xFF {
+        return Err(format!("import '{}' has a name longer than 255 bytes", spec));
+    }
+    let entry = match entry.parse::<u16>() {
+        Ok(_) => ImportEntry::Ordinal(parse_ordinal(entry)?),
+        Err(_) => ImportEntry::Name(String::from(entry))
+    };
+    let symbol = match (symbol, &entry) {
+        (Some(symbol), _) if !symbol.is_empty() => String::from(symbol),
+        (None, ImportEntry::Name(name)) => name.clone(),
+        _ => return Err(format!("import '{}' by ordinal needs an internal name", spec))
+    };
+    Ok(Import { symbol, module: String::from(module), entry })
+}
+
+/// Parses the EXPORTS and IMPORTS statements of a .def file.
+pub fn parse_def(text: &str) -> Result<Definitions, String> {
+    let mut defs = Definitions::default();
+    let mut section = String::new();
+    for (line_idx, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").replace(" = ", "=");
+        let mut tokens = line.split_whitespace().peekable();
+        let first = match tokens.peek() {
+            Some(first) => *first,
+            None => continue
+        };
+        if STATEMENTS.contains(&first.to_ascii_uppercase().as_str()) {
+            section = first.to_ascii_uppercase();
+            tokens.next();
+            if tokens.peek().is_none() { continue }
+        }
+
+        let error = |msg: String| format!("line {}: {}", line_idx + 1, msg);
+        if section == "IMPORTS" {
+            defs.imports.push(parse_import(tokens.next().unwrap()).map_err(error)?);
+            continue
+        }
+        if section != "EXPORTS" { continue }
+        let (name, symbol) = parse_name(tokens.next().unwrap()).map_err(error)?;
+        let mut export = Export { name, symbol, ordinal: None, noname: false };
+        while let Some(token) = tokens.next() {
+            if let Some(ordinal) = token.strip_prefix('@') {
+                let ordinal = if ordinal.is_empty() { tokens.next().unwrap_or("") } else { ordinal };
+                export.ordinal = Some(parse_ordinal(ordinal).map_err(error)?);
+            } else if token.eq_ignore_ascii_case("NONAME") {
+                export.noname = true;
+            }
+            // RESIDENTNAME, DATA, PRIVATE and such change nothing here
+        }
+        defs.exports.push(export);
+    }
+    Ok(defs)
+}
+
+/// Hands out ordinals: explicit ones are kept, the rest get the lowest free
+/// ordinals in list order.
+pub fn assign_ordinals(exports: &[Export]) -> Result<Vec<u16>, String> {
+    let mut used = std::collections::HashSet::new();
+    for export in exports {
+        if let Some(ordinal) = export.ordinal {
+            if !used.insert(ordinal) {
+                return Err(format!("ordinal {} is exported twice", ordinal));
+            }
+        }
+    }
+    let mut next = 1u16;
+    let mut ordinals = Vec::new();
+    for export in exports {
+        match export.ordinal {
+            Some(ordinal) => ordinals.push(ordinal),
+            None => {
+                while used.contains(&next) {
+                    next = next.checked_add(1).ok_or("ran out of ordinals")?;
+                }
+                used.insert(next);
+                ordinals.push(next);
+            }
+        }
+    }
+    Ok(ordinals)
+}
@@ -0,0 +1,116 @@
+//! What can go wrong converting, with enough context to find it in the input.
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// An input file couldn't be read
+    Read { path: String, source: std::io::Error },
+    /// An input isn't an object file, or a broken one
+    Parse { path: String, source: object::Error },
+    /// Writing the output
+    Io(std::io::Error),
+    /// Reading back the merged object
+    Object(object::Error),
+    /// Building the merged object
+    Write(object::write::Error),
+    /// A section the conversion needs isn't there
+    MissingSection { name: String },
+    /// An option or the layout names an LE object there isn't
+    MissingObject { name: String, wanted_by: String },
+    /// A symbol the output refers to isn't defined, with the file, section
+    /// and offset of the reference if it's from a relocation
+    MissingSymbol { name: String, reference: Option<(String, String, u64)> },
+    /// A symbol that has to be in the output is defined outside every LE object
+    UnplacedSymbol { name: String },
+    /// Two inputs define the same global
+    DuplicateSymbol { name: String, first: String, second: String },
+    /// A relocation LE fixups can't express
+    UnsupportedRelocation { file: String, section: String, offset: u64, kind: String },
+    /// A relocation to something that isn't in any LE object
+    UnplacedTarget { file: String, section: String, offset: u64, target: String },
+    /// A relocation or an offset that doesn't fit where it goes
+    OffsetOverflow { file: String, section: String, offset: u64 },
+    /// The layout doesn't put any section in an LE object
+    NoSections,
+    /// `--gc-sections` has nothing to start from
+    NoGcRoots,
+    /// A layout file that doesn't parse
+    Layout { path: String, message: String },
+    /// A `--stub` or `--bind` program that can't go in front of the LE header
+    Stub { path: String, message: String },
+    /// A file that should have an LE header doesn't
+    NotLe { offset: u64 },
+    /// An LE header pointing past the end of its file
+    Truncated { offset: u64, len: u64 },
+    /// Reading the DWARF or writing the debug info
+    Debug(Box<dyn std::error::Error>),
+    /// Bad options
+    Invalid(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Read { path, source } => write!(f, "{}: {}", path, source),
+            Error::Parse { path, source } => write!(f, "{}: {}", path, source),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Object(err) => write!(f, "merged object: {}", err),
+            Error::Write(err) => write!(f, "building merged object: {}", err),
+            Error::MissingSection { name } => write!(f, "there's no section {}", name),
+            Error::MissingObject { name, wanted_by } => write!(f, "{}: there's no LE object {}", wanted_by, name),
+            Error::MissingSymbol { name, reference: None } => write!(f, "symbol {} isn't defined", name),
+            Error::MissingSymbol { name, reference: Some((file, section, offset)) } =>
+                write!(f, "{}: {}+0x{:x}: undefined reference to {}", file, section, offset, name),
+            Error::UnplacedSymbol { name } => write!(f, "{} isn't in any LE object", name),
+            Error::DuplicateSymbol { name, first, second } => write!(f, "{} is defined in both {} and {}", name, first, second),
+            Error::UnsupportedRelocation { file, section, offset, kind } =>
+                write!(f, "{}: {}+0x{:x}: unsupported relocation {}", file, section, offset, kind),
+            Error::UnplacedTarget { file, section, offset, target } =>
+                write!(f, "{}: {}+0x{:x}: relocation refers to {}, which isn't in any LE object", file, section, offset, target),
+            Error::OffsetOverflow { file, section, offset } =>
+                write!(f, "{}: {}+0x{:x}: offset out of range", file, section, offset),
+            Error::NoSections => write!(f, "no sections to put in the LE objects"),
+            Error::NoGcRoots => write!(f, "--gc-sections: no entry point, exports or --keep symbols, everything would go"),
+            Error::Layout { path, message } => write!(f, "{}: {}", path, message),
+            Error::Stub { path, message } => write!(f, "{}: {}", path, message),
+            Error::NotLe { offset } => write!(f, "no LE or LX header at 0x{:x}", offset),
+            Error::Truncated { offset, len } => write!(f, "0x{:x} bytes at 0x{:x} are past the end of the file", len, offset),
+            Error::Debug(err) => write!(f, "debug info: {}", err),
+            Error::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Read { source, .. } => Some(source),
+            Error::Parse { source, .. } => Some(source),
+            Error::Io(err) => Some(err),
+            Error::Object(err) => Some(err),
+            Error::Write(err) => Some(err),
+            Error::Debug(err) => Some(err.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self { Error::Io(err) }
+}
+
+impl From<object::Error> for Error {
+    fn from(err: object::Error) -> Self { Error::Object(err) }
+}
+
+impl From<object::write::Error> for Error {
+    fn from(err: object::write::Error) -> Self { Error::Write(err) }
+}
+
+// The debug helpers report with boxed errors
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(err: Box<dyn std::error::Error>) -> Self { Error::Debug(err) }
+}
@@ -0,0 +1,227 @@
+//! EXEPACK2 compression of LX pages.
+//!
+//! A compressed page is a sequence of blocks, each told apart by the low two
+//! bits of its first byte:
+//!
+//! - `00`: with a non-zero first byte, `byte >> 2` literal bytes follow.
+//!   With a zero first byte, the next byte is a count and the one after it
+//!   is repeated that many times.
+//! - `01`: a 16-bit word, `lit` (bits 2-3) literal bytes, then a copy of
+//!   `len + 3` (bits 4-6) bytes from `off` (bits 7-15) bytes back.
+//! - `10`: a 16-bit word, a copy of `len + 3` (bits 2-3) bytes from `off`
+//!   (bits 4-15) bytes back.
+//! - `11`: a 24-bit word, `lit` (bits 2-5) literal bytes, then a copy of
+//!   `len` (bits 6-11) bytes from `off` (bits 12-23) bytes back.
+//!
+//! Copies here never overlap what they're copying, runs use the repeat block
+//! instead, so it doesn't matter how a loader copies.
+
+use std::collections::HashMap;
+
+const MAX_OFFSET: usize = 0xFFF;
+const MAX_LENGTH: usize = 0x3F;
+const MAX_LITERALS: usize = 0x3F;
+const MAX_RUN: usize = 0xFF;
+// Shorter runs are as cheap as literals or a copy
+const MIN_RUN: usize = 4;
+const MIN_LENGTH: usize = 3;
+// How many earlier spots with the same 3 bytes are tried
+const MAX_CANDIDATES: usize = 64;
+
+/// Compresses one page. The result can be bigger than the page, it's up to
+/// the caller to keep whichever is smaller.
+pub fn compress(page: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    // Where each 3 bytes were seen, most recent last
+    let mut seen: HashMap<[u8; 3], Vec<usize>> = HashMap::new();
+    let mut literals_start = 0;
+    let mut pos = 0;
+    while pos < page.len() {
+        let run = page[pos..].iter().take(MAX_RUN).take_while(|byte| **byte == page[pos]).count();
+        if run >= MIN_RUN {
+            push_literals(&mut out, &page[literals_start..pos]);
+            out.extend_from_slice(&[0, run as u8, page[pos]]);
+            remember(&mut seen, page, pos, pos + run);
+            pos += run;
+            literals_start = pos;
+            continue
+        }
+        match longest_match(&seen, page, pos) {
+            Some((offset, length)) => {
+                push_copy(&mut out, &page[literals_start..pos], offset, length);
+                remember(&mut seen, page, pos, pos + length);
+                pos += length;
+                literals_start = pos;
+            }
+            None => {
+                remember(&mut seen, page, pos, pos + 1);
+                pos += 1;
+            }
+        }
+    }
+    push_literals(&mut out, &page[literals_start..]);
+    out
+}
+
+fn remember(seen: &mut HashMap<[u8; 3], Vec<usize>>, page: &[u8], from: usize, to: usize) {
+    for pos in from..to.min(page.len().saturating_sub(2)) {
+        seen.entry([page[pos], page[pos + 1], page[pos + 2]]).or_default().push(pos);
+    }
+}
+
+/// The longest earlier copy of what's at `pos`, as (offset back, length).
+fn longest_match(seen: &HashMap<[u8; 3], Vec<usize>>, page: &[u8], pos: usize) -> Option<(usize, usize)> {
+    let key = page.get(pos..pos + 3)?;
+    let candidates = seen.get(key)?;
+    let mut best: Option<(usize, usize)> = None;
+    for &start in candidates.iter().rev().take(MAX_CANDIDATES) {
+        let offset = pos - start;
+        if offset > MAX_OFFSET { break }
+        let limit = offset.min(MAX_LENGTH).min(page.len() - pos);
+        let length = (0..limit).take_while(|idx| page[start + idx] == page[pos + idx]).count();
+        if length >= MIN_LENGTH && best.is_none_or(|(_, best_length)| length > best_length) {
+            best = Some((offset, length));
+        }
+    }
+    best
+}
+
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        out.push((chunk.len() << 2) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Writes `literals` and then a copy, in the smallest block that fits.
+fn push_copy(out: &mut Vec<u8>, literals: &[u8], offset: usize, length: usize) {
+    // Only a few literals fit in a copy block, the rest go first on their own
+    let attached = if offset <= 0x1FF && length <= 10 { literals.len() % 4 } else { literals.len() % 16 };
+    let (alone, literals) = literals.split_at(literals.len() - attached);
+    push_literals(out, alone);
+    if literals.is_empty() && length <= 6 {
+        let word = (offset << 4) | ((length - 3) << 2) | 0b10;
+        out.extend_from_slice(&(word as u16).to_le_bytes());
+    } else if offset <= 0x1FF && length <= 10 {
+        let word = (offset << 7) | ((length - 3) << 4) | (literals.len() << 2) | 0b01;
+        out.extend_from_slice(&(word as u16).to_le_bytes());
+        out.extend_from_slice(literals);
+    } else {
+        let word = (offset << 12) | (length << 6) | (literals.len() << 2) | 0b11;
+        out.extend_from_slice(&word.to_le_bytes()[..3]);
+        out.extend_from_slice(literals);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compress;
+
+    /// Unpacks a page block by block, as described at the top of the file.
+    fn decompress(packed: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut pos = 0;
+        let copy = |out: &mut Vec<u8>, offset: usize, length: usize| {
+            assert!(offset >= 1 && offset <= out.len(), "copy from {} back with {} bytes out", offset, out.len());
+            for _ in 0..length {
+                out.push(out[out.len() - offset]);
+            }
+        };
+        while pos < packed.len() {
+            let first = packed[pos];
+            match first & 0b11 {
+                0b00 if first == 0 => {
+                    let (count, byte) = (packed[pos + 1] as usize, packed[pos + 2]);
+                    out.extend(std::iter::repeat_n(byte, count));
+                    pos += 3;
+                }
+                0b00 => {
+                    let count = (first >> 2) as usize;
+                    out.extend_from_slice(&packed[pos + 1..pos + 1 + count]);
+                    pos += 1 + count;
+                }
+                0b01 => {
+                    let word = u16::from_le_bytes([packed[pos], packed[pos + 1]]) as usize;
+                    let literals = (word >> 2) & 0b11;
+                    out.extend_from_slice(&packed[pos + 2..pos + 2 + literals]);
+                    copy(&mut out, word >> 7, ((word >> 4) & 0b111) + 3);
+                    pos += 2 + literals;
+                }
+                0b10 => {
+                    let word = u16::from_le_bytes([packed[pos], packed[pos + 1]]) as usize;
+                    copy(&mut out, word >> 4, ((word >> 2) & 0b11) + 3);
+                    pos += 2;
+                }
+                _ => {
+                    let word = u32::from_le_bytes([packed[pos], packed[pos + 1], packed[pos + 2], 0]) as usize;
+                    let literals = (word >> 2) & 0xF;
+                    out.extend_from_slice(&packed[pos + 3..pos + 3 + literals]);
+                    copy(&mut out, word >> 12, (word >> 6) & 0x3F);
+                    pos += 3 + literals;
+                }
+            }
+        }
+        out
+    }
+
+    fn round_trip(page: &[u8]) -> Vec<u8> {
+        let packed = compress(page);
+        assert_eq!(decompress(&packed), page);
+        packed
+    }
+
+    /// Bytes that don't repeat in any useful way.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545F491u32;
+        (0..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect()
+    }
+
+    #[test]
+    fn zero_runs() {
+        assert!(round_trip(&[0; 0x1000]).len() < 0x40);
+        let mut page = vec![0; 0x300];
+        page[0x100] = 7;
+        page[0x2FF] = 9;
+        assert!(round_trip(&page).len() < 0x20);
+    }
+
+    #[test]
+    fn repeated_patterns() {
+        let pattern = b"\x55\x89\xE5\x83\xEC\x08\xE8\x00\x00\x00\x00\xC9\xC3";
+        let page: Vec<u8> = pattern.iter().copied().cycle().take(0x800).collect();
+        assert!(round_trip(&page).len() < 0x200);
+        // Short repeats with a few bytes between them, for the small blocks
+        let mut page = Vec::new();
+        for idx in 0..200u8 {
+            page.extend_from_slice(&[idx, 1, 2, 3, 4, idx, idx ^ 0x5A]);
+        }
+        round_trip(&page);
+    }
+
+    #[test]
+    fn incompressible() {
+        let page = noise(0x1000);
+        let packed = round_trip(&page);
+        // Only the literal counts are added
+        assert!(packed.len() <= page.len() + page.len().div_ceil(0x3F));
+    }
+
+    #[test]
+    fn full_page() {
+        let mut page = noise(0x400);
+        page.extend_from_slice(&[0; 0x300]);
+        page.extend_from_within(0x100..0x500);
+        page.extend(std::iter::repeat_n(0x90, 0x200));
+        page.extend(noise(0x1000 - page.len()).iter().map(|byte| byte & 0x3));
+        assert_eq!(page.len(), 0x1000);
+        assert!(round_trip(&page).len() < page.len());
+        round_trip(&[]);
+        round_trip(&[1]);
+        round_trip(&[1, 2, 3, 1, 2, 3]);
+    }
+}
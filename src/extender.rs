@@ -0,0 +1,100 @@
+//! Per-extender choices for the header, objects and fixups.
+//!
+//! Every extender reads LE a little differently. DOS/32A has a complete
+//! LE/LX loader, the others are mostly tested against what WLINK emits, so
+//! their profiles stick to that: one source per fixup record.
+
+pub struct Profile {
+    pub name: &'static str,
+    /// Target OS header field, 1 is OS/2
+    pub target_os: u16,
+    pub module_flags: u32,
+    pub text_flags: u32,
+    pub data_flags: u32,
+    /// Merge fixups in a page that share a target into one record with a
+    /// source list
+    pub source_lists: bool,
+    /// Always write 32-bit target offsets, even when 16 bits would do
+    pub offset32: bool,
+    /// Alignment of the LE header after a `--bind` extender's MZ image
+    pub bind_align: usize,
+    /// Mark all-zero pages zero-filled in the page map instead of storing them
+    pub zero_filled_pages: bool,
+}
+
+// What WLINK writes for `format os2 le`, which Open Watcom's wlsystem.lnk
+// uses for dos4g, pmodew, causeway and dos32a alike, so every profile has
+// these. Module: compatible with PM windowing
+const MODULE_FLAGS: u32 = 0x00000200;
+// Readable, executable, preload, 32-bit
+const TEXT_FLAGS: u32 = 0x00002045;
+// Readable, writable, preload, 32-bit
+const DATA_FLAGS: u32 = 0x00002043;
+
+pub const PROFILES: &[Profile] = &[
+    Profile {
+        name: "dos32a",
+        target_os: 1,
+        module_flags: MODULE_FLAGS,
+        text_flags: TEXT_FLAGS,
+        data_flags: DATA_FLAGS,
+        source_lists: true,
+        offset32: false,
+        bind_align: 1,
+        zero_filled_pages: true,
+    },
+    Profile {
+        name: "dos4gw",
+        target_os: 1,
+        module_flags: MODULE_FLAGS,
+        text_flags: TEXT_FLAGS,
+        data_flags: DATA_FLAGS,
+        source_lists: false,
+        offset32: false,
+        // The stub only runs DOS4GW.EXE, which goes by e_lfanew
+        bind_align: 16,
+        zero_filled_pages: false,
+    },
+    Profile {
+        name: "pmodew",
+        target_os: 1,
+        module_flags: MODULE_FLAGS,
+        text_flags: TEXT_FLAGS,
+        data_flags: DATA_FLAGS,
+        source_lists: false,
+        offset32: true,
+        bind_align: 1,
+        zero_filled_pages: false,
+    },
+    Profile {
+        name: "causeway",
+        target_os: 1,
+        module_flags: MODULE_FLAGS,
+        text_flags: TEXT_FLAGS,
+        data_flags: DATA_FLAGS,
+        source_lists: false,
+        offset32: false,
+        bind_align: 1,
+        zero_filled_pages: false,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Profile> {
+    PROFILES.iter().find(|profile| profile.name.eq_ignore_ascii_case(name))
+}
+
+/// Not an extender: what the Windows VMM loader wants for a VxD, used by
+/// `--vxd`. The object flags come from the VxD layout instead.
+pub const VXD: Profile = Profile {
+    name: "vxd",
+    // Windows 386
+    target_os: 4,
+    // Module type: virtual device driver
+    module_flags: 0x00038000,
+    text_flags: 0,
+    data_flags: 0,
+    source_lists: false,
+    offset32: false,
+    bind_align: 16,
+    zero_filled_pages: false,
+};
@@ -0,0 +1,55 @@
+//! `--gc-sections`: dropping input sections nothing refers to.
+//!
+//! A section is live if it's a root or a live section has a relocation
+//! against it, or against a symbol defined in it. Constructor and destructor
+//! tables are always roots, they're only ever found by the startup code
+//! walking them.
+
+use std::collections::HashSet;
+
+use object::{Object, ObjectSection, ObjectSymbol, RelocationTarget, SectionIndex};
+
+use crate::layout::{glob_match, TABLES};
+
+/// Returns the sections reachable from `roots` and the always kept ones.
+pub fn live_sections(obj_file: &object::File, roots: &[SectionIndex]) -> HashSet<SectionIndex> {
+    let mut live: HashSet<SectionIndex> = roots.iter().copied().collect();
+    let mut work: Vec<SectionIndex> = roots.to_vec();
+    for section in obj_file.sections() {
+        let name = section.name().unwrap_or("");
+        let kept = TABLES.iter().any(|table| glob_match(table.sorted, name) || glob_match(table.unsorted, name));
+        if kept && live.insert(section.index()) {
+            work.push(section.index());
+        }
+    }
+    while let Some(index) = work.pop() {
+        let section = match obj_file.section_by_index(index) {
+            Ok(section) => section,
+            Err(_) => continue
+        };
+        for (_, rel) in section.relocations() {
+            let target = match rel.target() {
+                RelocationTarget::Symbol(idx) => match obj_file.symbol_by_index(idx) {
+                    Ok(sym) => sym.section_index(),
+                    Err(_) => None
+                },
+                RelocationTarget::Section(idx) => Some(idx),
+                _ => None
+            };
+            if let Some(target) = target {
+                if live.insert(target) {
+                    work.push(target);
+                }
+            }
+        }
+    }
+    live
+}
+
+/// Sections defining any of `names`.
+pub fn symbol_sections(obj_file: &object::File, names: &[&str]) -> Vec<SectionIndex> {
+    obj_file.symbols()
+        .filter(|sym| !sym.is_undefined() && sym.name().is_ok_and(|name| names.contains(&name)))
+        .filter_map(|sym| sym.section_index())
+        .collect()
+}
@@ -0,0 +1,402 @@
+//! Which input sections go into which LE object.
+//!
+//! A layout is a list of objects, each with rules picking input sections.
+//! Name rules are tried first, across every object, so a specifically named
+//! section lands in its own object even when an earlier object takes that
+//! kind of section. Sections left over then go by kind. Inside an object,
+//! sections are placed in rule order, then in input order, except that bss
+//! taken by kind goes after everything else so it's in one piece.
+//!
+//! Besides the built-in layouts, one can be read from a TOML file:
+//!
+//!   start = { object = "CODE", offset = 0 }
+//!   stack = { object = "DATA", offset = 0x80008 }
+//!
+//!   [[object]]
+//!   name = "CODE"
+//!   code = true
+//!   base = 0
+//!   min_size = 0x80000
+//!   align = 16
+//!   sections = [".text.start", ".text.hot*"]
+//!   kinds = ["text"]
+//!
+//! A `sections` pattern written `SORT(pattern)` places its matches sorted by
+//! name. `flags` sets the object table flags outright, otherwise they're made from
+//! `code`, `writable`, `preload`, `discardable` and `shared`.
+
+use object::{Object, ObjectSection, SectionIndex, SectionKind};
+
+use crate::extender::Profile;
+
+// Object bases and minimum virtual sizes for executables. The stack lives at
+// the end of the data object below INITIAL_ESP.
+const TEXT_OBJECT_BASE: u32 = 0x00000000;
+const TEXT_OBJECT_SIZE: u32 = 0x00080000;
+const DATA_OBJECT_BASE: u32 = 0x00070000;
+const DATA_OBJECT_SIZE: u32 = 0x00080080;
+const INITIAL_ESP: u32 = 0x00080008;
+
+/// The kinds of input section that get loaded
+const LOADED_KINDS: &[SectionKind] = &[
+    SectionKind::Text,
+    SectionKind::Data,
+    SectionKind::ReadOnlyData,
+    SectionKind::ReadOnlyString,
+    SectionKind::UninitializedData,
+    SectionKind::Elf(object::elf::SHT_INIT_ARRAY),
+    SectionKind::Elf(object::elf::SHT_FINI_ARRAY),
+    SectionKind::Elf(object::elf::SHT_PREINIT_ARRAY),
+];
+
+/// A table of function pointers the startup code walks, and the symbols
+/// defined at its start and end. Prioritized parts come first, sorted by
+/// name, which sorts by priority as compilers zero-pad it. `.ctors` and
+/// `.dtors` are walked backwards and number their parts 65535 minus the
+/// priority, so like GNU ld they have the plain part first instead.
+pub struct Table {
+    pub sorted: &'static str,
+    pub unsorted: &'static str,
+    pub unsorted_first: bool,
+    pub start: &'static str,
+    pub end: &'static str,
+}
+
+pub const TABLES: &[Table] = &[
+    Table { sorted: ".preinit_array.*", unsorted: ".preinit_array", unsorted_first: false, start: "__preinit_array_start", end: "__preinit_array_end" },
+    Table { sorted: ".init_array.*", unsorted: ".init_array", unsorted_first: false, start: "__init_array_start", end: "__init_array_end" },
+    Table { sorted: ".fini_array.*", unsorted: ".fini_array", unsorted_first: false, start: "__fini_array_start", end: "__fini_array_end" },
+    Table { sorted: ".ctors.*", unsorted: ".ctors", unsorted_first: true, start: "__ctors_start", end: "__ctors_end" },
+    Table { sorted: ".dtors.*", unsorted: ".dtors", unsorted_first: true, start: "__dtors_start", end: "__dtors_end" },
+];
+
+/// Rules taking sections of `kinds`, bss last.
+fn kind_rules(kinds: &[SectionKind]) -> Vec<SectionRule> {
+    let (bss, other): (Vec<SectionKind>, Vec<SectionKind>) = kinds.iter().partition(|kind| **kind == SectionKind::UninitializedData);
+    [other, bss].into_iter().filter(|kinds| !kinds.is_empty()).map(SectionRule::Kinds).collect()
+}
+
+/// Rules putting each table together, in the order of `TABLES`.
+fn table_rules() -> Vec<SectionRule> {
+    TABLES.iter()
+        .flat_map(|table| {
+            let (sorted, unsorted) = (SectionRule::Sorted(String::from(table.sorted)), SectionRule::Name(String::from(table.unsorted)));
+            if table.unsorted_first { [unsorted, sorted] } else { [sorted, unsorted] }
+        })
+        .collect()
+}
+
+pub enum SectionRule {
+    /// Sections whose name matches a glob, `*` and `?` only
+    Name(String),
+    /// Same, but sorted by name instead of in input order
+    Sorted(String),
+    /// Sections of any of these kinds not taken by a name rule
+    Kinds(Vec<SectionKind>),
+}
+
+pub struct ObjectSpec {
+    /// Name of the merged section in `new.elf`
+    pub name: String,
+    /// Object table flags
+    pub flags: u32,
+    /// Relocation base address
+    pub base: u32,
+    /// Minimum virtual size
+    pub min_size: u32,
+    pub code: bool,
+    /// Minimum alignment of each input section inside the object
+    pub align: u64,
+    /// Write the object even if no section goes in it
+    pub keep_empty: bool,
+    pub rules: Vec<SectionRule>,
+}
+
+pub struct Layout {
+    pub objects: Vec<ObjectSpec>,
+    /// Initial CS:EIP, as (object name, offset)
+    pub start: Option<(String, u32)>,
+    /// Initial SS:ESP, as (object name, offset)
+    pub stack: Option<(String, u32)>,
+}
+
+/// The usual executable: code in `.text` and everything else in `.data`,
+/// with `.text.start` at the very start of the code.
+pub fn default_layout(profile: &Profile) -> Layout {
+    Layout {
+        objects: vec![
+            ObjectSpec {
+                name: String::from(".text"),
+                flags: profile.text_flags,
+                base: TEXT_OBJECT_BASE,
+                min_size: TEXT_OBJECT_SIZE,
+                code: true,
+                align: 1,
+                keep_empty: false,
+                rules: vec![
+                    SectionRule::Name(String::from(".text.start")),
+                    SectionRule::Kinds(vec![SectionKind::Text]),
+                ],
+            },
+            ObjectSpec {
+                name: String::from(".data"),
+                flags: profile.data_flags,
+                base: DATA_OBJECT_BASE,
+                min_size: DATA_OBJECT_SIZE,
+                code: false,
+                align: 1,
+                keep_empty: false,
+                rules: table_rules().into_iter()
+                    .chain(kind_rules(&[SectionKind::Data, SectionKind::ReadOnlyData, SectionKind::ReadOnlyString, SectionKind::UninitializedData]))
+                    .collect(),
+            },
+        ],
+        start: Some((String::from(".text"), 0)),
+        stack: Some((String::from(".data"), INITIAL_ESP)),
+    }
+}
+
+// Readable, writable, executable, preload, 32-bit
+const VXD_LOCKED_FLAGS: u32 = 0x00002047;
+// Same, but dropped once the device is initialized
+const VXD_INIT_FLAGS: u32 = 0x00002057;
+// Readable, writable, executable, 32-bit, paged in on demand
+const VXD_PAGEABLE_FLAGS: u32 = 0x00002007;
+
+fn vxd_object(name: &str, flags: u32, rules: Vec<SectionRule>) -> ObjectSpec {
+    ObjectSpec {
+        name: String::from(name),
+        flags,
+        base: 0,
+        min_size: 0,
+        code: true,
+        align: 1,
+        keep_empty: false,
+        rules,
+    }
+}
+
+fn name_rules(patterns: &[&str]) -> Vec<SectionRule> {
+    patterns.iter().map(|pattern| SectionRule::Name(String::from(*pattern))).collect()
+}
+
+/// A Windows VxD, grouped like LINK386 does with the DDK's segment classes:
+/// locked code and data in LCODE, init-time code and data in ICODE, and
+/// pageable code and data in PCODE. Sections with no VxD name are locked.
+/// VxDs have no entry point or stack of their own.
+pub fn vxd_layout() -> Layout {
+    let mut locked = name_rules(&["_LTEXT*", "_LDATA*", ".ltext*", ".ldata*"]);
+    locked.extend(table_rules());
+    locked.extend(kind_rules(LOADED_KINDS));
+    Layout {
+        objects: vec![
+            vxd_object("LCODE", VXD_LOCKED_FLAGS, locked),
+            vxd_object("ICODE", VXD_INIT_FLAGS, name_rules(&["_ITEXT*", "_IDATA*", ".itext*", ".idata*"])),
+            vxd_object("PCODE", VXD_PAGEABLE_FLAGS, name_rules(&["_PTEXT*", "_PDATA*", ".ptext*", ".pdata*"])),
+        ],
+        start: None,
+        stack: None,
+    }
+}
+
+// Object table flag bits
+const FLAG_READABLE: u32 = 0x0001;
+const FLAG_WRITABLE: u32 = 0x0002;
+const FLAG_EXECUTABLE: u32 = 0x0004;
+const FLAG_DISCARDABLE: u32 = 0x0010;
+const FLAG_SHARED: u32 = 0x0020;
+const FLAG_PRELOAD: u32 = 0x0040;
+const FLAG_BIG: u32 = 0x2000;
+
+fn get_bool(table: &toml::Table, key: &str, default: bool) -> Result<bool, String> {
+    match table.get(key) {
+        Some(value) => value.as_bool().ok_or_else(|| format!("'{}' should be true or false", key)),
+        None => Ok(default)
+    }
+}
+
+fn get_u32(table: &toml::Table, key: &str, default: u32) -> Result<u32, String> {
+    match table.get(key) {
+        Some(value) => value.as_integer()
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| format!("'{}' should be a number from 0 to 0xFFFFFFFF", key)),
+        None => Ok(default)
+    }
+}
+
+fn get_strings(table: &toml::Table, key: &str) -> Result<Vec<String>, String> {
+    match table.get(key) {
+        Some(toml::Value::Array(values)) => values.iter()
+            .map(|value| value.as_str().map(String::from).ok_or_else(|| format!("'{}' should only hold strings", key)))
+            .collect(),
+        Some(_) => Err(format!("'{}' should be a list of strings", key)),
+        None => Ok(Vec::new())
+    }
+}
+
+/// The section kinds a kind name in a layout file stands for. Merged
+/// strings (`.rodata.str1.1`) are read-only data too.
+fn parse_kind(name: &str) -> Result<&'static [SectionKind], String> {
+    match name {
+        "text" => Ok(&[SectionKind::Text]),
+        "data" => Ok(&[SectionKind::Data]),
+        "rodata" => Ok(&[SectionKind::ReadOnlyData, SectionKind::ReadOnlyString]),
+        "bss" => Ok(&[SectionKind::UninitializedData]),
+        _ => Err(format!("unknown section kind '{}', should be text, data, rodata or bss", name))
+    }
+}
+
+fn parse_object(table: &toml::Table) -> Result<ObjectSpec, String> {
+    let name = match table.get("name").and_then(|name| name.as_str()) {
+        Some(name) if !name.is_empty() => String::from(name),
+        _ => return Err(String::from("every object needs a name"))
+    };
+    let error = |msg: String| format!("object {}: {}", name, msg);
+    let code = get_bool(table, "code", false).map_err(error)?;
+    let flags = match table.get("flags") {
+        Some(_) => get_u32(table, "flags", 0).map_err(error)?,
+        None => {
+            let mut flags = FLAG_READABLE | FLAG_BIG;
+            if code { flags |= FLAG_EXECUTABLE; }
+            if get_bool(table, "writable", !code).map_err(error)? { flags |= FLAG_WRITABLE; }
+            if get_bool(table, "preload", true).map_err(error)? { flags |= FLAG_PRELOAD; }
+            if get_bool(table, "discardable", false).map_err(error)? { flags |= FLAG_DISCARDABLE; }
+            if get_bool(table, "shared", false).map_err(error)? { flags |= FLAG_SHARED; }
+            flags
+        }
+    };
+    let align = get_u32(table, "align", 1).map_err(error)?;
+    if !align.is_power_of_two() {
+        return Err(error(String::from("'align' should be a power of two")));
+    }
+    let mut rules: Vec<SectionRule> = get_strings(table, "sections").map_err(error)?
+        .into_iter()
+        .map(|pattern| match pattern.strip_prefix("SORT(").and_then(|rest| rest.strip_suffix(')')) {
+            Some(sorted) => SectionRule::Sorted(String::from(sorted)),
+            None => SectionRule::Name(pattern)
+        })
+        .collect();
+    let kinds = get_strings(table, "kinds").map_err(error)?
+        .iter()
+        .map(|kind| parse_kind(kind))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(error)?
+        .concat();
+    rules.extend(kind_rules(&kinds));
+    Ok(ObjectSpec {
+        flags,
+        base: get_u32(table, "base", 0).map_err(error)?,
+        min_size: get_u32(table, "min_size", 0).map_err(error)?,
+        code,
+        align: align as u64,
+        keep_empty: get_bool(table, "keep_empty", false).map_err(error)?,
+        rules,
+        name,
+    })
+}
+
+fn parse_start(table: &toml::Table, key: &str) -> Result<Option<(String, u32)>, String> {
+    let start = match table.get(key) {
+        Some(toml::Value::Table(start)) => start,
+        Some(_) => return Err(format!("'{}' should be {{ object = \"NAME\", offset = 0 }}", key)),
+        None => return Ok(None)
+    };
+    let object = start.get("object").and_then(|object| object.as_str())
+        .ok_or_else(|| format!("'{}' needs an object", key))?;
+    Ok(Some((String::from(object), get_u32(start, "offset", 0)?)))
+}
+
+/// Reads a layout file, see the top of this file.
+pub fn parse_layout(text: &str) -> Result<Layout, String> {
+    let table: toml::Table = text.parse().map_err(|err: toml::de::Error| err.to_string())?;
+    let objects = match table.get("object") {
+        Some(toml::Value::Array(objects)) => objects.iter()
+            .map(|object| object.as_table().ok_or_else(|| String::from("[[object]] entries should be tables")).and_then(parse_object))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(String::from("no [[object]] entries"))
+    };
+    for (idx, object) in objects.iter().enumerate() {
+        if objects[..idx].iter().any(|other| other.name == object.name) {
+            return Err(format!("object {} is there twice", object.name));
+        }
+    }
+    Ok(Layout {
+        objects,
+        start: parse_start(&table, "start")?,
+        stack: parse_start(&table, "stack")?,
+    })
+}
+
+/// Matches `name` against a glob with `*` (any run) and `?` (any one byte).
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    // Where to pick up after the last `*`, if the rest doesn't match
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            },
+            Some(c) if *c == b'?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            },
+            _ => match backtrack {
+                Some((star, from)) => {
+                    p = star + 1;
+                    n = from + 1;
+                    backtrack = Some((star, from + 1));
+                },
+                None => return false
+            }
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+/// Returns the input sections for each object of the layout, in placement
+/// order. Sections no rule picks are left out.
+pub fn assign_sections(obj_file: &object::File, layout: &Layout) -> Vec<Vec<SectionIndex>> {
+    let candidates: Vec<(SectionIndex, String, SectionKind)> = obj_file.sections()
+        .filter(|section| LOADED_KINDS.contains(&section.kind()))
+        .map(|section| (section.index(), String::from(section.name().unwrap_or("")), section.kind()))
+        .collect();
+    let mut taken = vec![false; candidates.len()];
+    let mut assigned: Vec<Vec<SectionIndex>> = layout.objects.iter().map(|_| Vec::new()).collect();
+
+    for (obj, spec) in layout.objects.iter().enumerate() {
+        for rule in spec.rules.iter() {
+            let (pattern, sorted) = match rule {
+                SectionRule::Name(pattern) => (pattern, false),
+                SectionRule::Sorted(pattern) => (pattern, true),
+                SectionRule::Kinds(_) => continue
+            };
+            let mut matched = Vec::new();
+            for (idx, (index, name, _)) in candidates.iter().enumerate() {
+                if !taken[idx] && glob_match(pattern, name) {
+                    taken[idx] = true;
+                    matched.push((name, *index));
+                }
+            }
+            if sorted {
+                matched.sort_by_key(|(name, _)| *name);
+            }
+            assigned[obj].extend(matched.into_iter().map(|(_, index)| index));
+        }
+    }
+    for (obj, spec) in layout.objects.iter().enumerate() {
+        for rule in spec.rules.iter() {
+            if let SectionRule::Kinds(kinds) = rule {
+                for (idx, (index, _, kind)) in candidates.iter().enumerate() {
+                    if !taken[idx] && kinds.contains(kind) {
+                        taken[idx] = true;
+                        assigned[obj].push(*index);
+                    }
+                }
+            }
+        }
+    }
+    assigned
+}
@@ -1,145 +1,294 @@
 use std::env;
 use std::fs;
-use std::io::{Write, Seek, BufWriter};
+use std::io::{Write, Seek};
 use std::os::unix::prelude::FileExt;
 
-use object::{Object, ObjectSection};
+use object::{Object, ObjectSection, SectionIndex};
 use object::read::*;
 
-const LE_STUB: &[u8; 563] = include_bytes!("lestub");
-const LE_HEADER_OFF: isize = 0x80;
+use error::Error;
 
-fn print_section_relocations(section: &object::Section, obj_file: &object::File) {
+mod checksum;
+mod debug;
+mod def;
+mod error;
+mod exepack;
+mod extender;
+mod gc;
+mod layout;
+mod merge;
+mod stub;
+mod watcom;
+
+fn print_section_relocations(section: &object::Section, obj_file: &object::File) -> error::Result<()> {
     for (ind, rel) in section.relocations() {
         print!("ind: {:x}\t", ind);
-        match rel.kind() {
-            object::RelocationKind::Absolute => {
-                print!("Absolute\tsize: {}\ttarget: ", rel.size());
-                match rel.target() {
-                    object::RelocationTarget::Symbol(s) => {
-                        let sym = obj_file.symbol_by_index(s).unwrap();
-                        let sym_sec_idx = sym.section_index();
-                        let sym_sec = if let Some(idx) = sym_sec_idx {
-                            Some(obj_file.section_by_index(idx).unwrap())
-                        } else {
-                            None
-                        };
-                        let sym_sec_name = if let Some(sec) = &sym_sec {
-                            sec.name().unwrap()
-                        } else {
-                            "None"
-                        };
-                        let implicit_addend = if rel.has_implicit_addend() {
-                            let mut arr: [u8; 4] = [0; 4];
-                            arr.copy_from_slice(&section.data().unwrap()[ind as usize..ind as usize+4]);
-                            u32::from_le_bytes(arr)
-                        } else {
-                            0
-                        };
-                        println!("{} (0x{:04x}) (in {})\taddend: {}\timplicit addend: {}", sym.name().unwrap(), sym.address(), sym_sec_name, rel.addend(), implicit_addend);
-                    },
-                    object::RelocationTarget::Section(sec) => println!("{}", obj_file.section_by_index(sec).unwrap().name().unwrap()),
-                    object::RelocationTarget::Absolute => println!("Absolute"),
-                    _ => println!("Err"),
-                };
-            },
+        let kind = match rel.kind() {
+            object::RelocationKind::Absolute => "Absolute",
             object::RelocationKind::PltRelative |
-            object::RelocationKind::Relative => {
-                print!("Relative\tsize: {}\ttarget: ", rel.size());
-                match rel.target() {
-                    object::RelocationTarget::Symbol(s) => {
-                        let sym = obj_file.symbol_by_index(s).unwrap();
-                        let sym_sec_idx = sym.section_index();
-                        let sym_sec = if let Some(idx) = sym_sec_idx {
-                            Some(obj_file.section_by_index(idx).unwrap())
-                        } else {
-                            None
-                        };
-                        let sym_sec_name = if let Some(sec) = &sym_sec {
-                            sec.name().unwrap()
-                        } else {
-                            "None"
-                        };
-                        let implicit_addend = if rel.has_implicit_addend() {
-                            let mut arr: [u8; 4] = [0; 4];
-                            arr.copy_from_slice(&section.data().unwrap()[ind as usize..ind as usize+4]);
-                            u32::from_le_bytes(arr)
-                        } else {
-                            0
-                        };
-                        println!("{} (0x{:04x}) (in {})\taddend: {}\timplicit addend: {}", sym.name().unwrap(), sym.address(), sym_sec_name, rel.addend(), implicit_addend);
-                    },
-                    object::RelocationTarget::Section(sec) => println!("{}", obj_file.section_by_index(sec).unwrap().name().unwrap()),
-                    object::RelocationTarget::Absolute => println!("Absolute"),
-                    _ => println!("Err"),
-                };
-            },
+            object::RelocationKind::Relative => "Relative",
             _ => {
                 println!("Unsupported Relocation Type");
+                continue
+            },
+        };
+        print!("{}\tsize: {}\ttarget: ", kind, rel.size());
+        match rel.target() {
+            object::RelocationTarget::Symbol(s) => {
+                let sym = obj_file.symbol_by_index(s)?;
+                let sym_sec_name = match sym.section_index() {
+                    Some(idx) => String::from(obj_file.section_by_index(idx)?.name()?),
+                    None => String::from("None")
+                };
+                let implicit_addend = if rel.has_implicit_addend() {
+                    section.data()?.get(ind as usize..ind as usize + 4)
+                        .and_then(|bytes| bytes.try_into().ok())
+                        .map_or(0, u32::from_le_bytes)
+                } else {
+                    0
+                };
+                println!("{} (0x{:04x}) (in {})\taddend: {}\timplicit addend: {}", sym.name()?, sym.address(), sym_sec_name, rel.addend(), implicit_addend);
             },
+            object::RelocationTarget::Section(sec) => println!("{}", obj_file.section_by_index(sec)?.name()?),
+            object::RelocationTarget::Absolute => println!("Absolute"),
+            _ => println!("Err"),
+        };
+    }
+    Ok(())
+}
+
+/// The merged section holding an LE object's contents.
+fn object_section<'data, 'file>(new_obj: &'file object::File<'data>, name: &str) -> error::Result<object::Section<'data, 'file>> {
+    new_obj.section_by_name(name).ok_or_else(|| Error::MissingSection { name: String::from(name) })
+}
+
+/// One entry of the object table.
+struct LEObject {
+    /// The merged section in `new.elf` holding the object's contents
+    name: String,
+    virtual_size: u32,
+    base: u32,
+    flags: u32,
+    code: bool,
+    /// One per page map entry, the object's contents past them are zeros
+    pages: Vec<LEPage>,
+}
+
+/// How a page of an object is stored, the page map entry's type.
+enum LEPage {
+    /// Stored as is, trailing zeros left out
+    Legal(Vec<u8>),
+    /// Not stored, the loader clears it
+    ZeroFilled,
+    /// Stored EXEPACK2 compressed, LX only
+    Compressed(Vec<u8>),
+}
+
+impl LEPage {
+    /// Page map entry type, LE and LX agree on these
+    fn flags(&self) -> u8 {
+        match self {
+            LEPage::Legal(_) => 0x00,
+            LEPage::ZeroFilled => 0x03,
+            LEPage::Compressed(_) => 0x05,
+        }
+    }
+
+    /// What goes in the data pages for it
+    fn stored(&self) -> &[u8] {
+        match self {
+            LEPage::Legal(data) | LEPage::Compressed(data) => data,
+            LEPage::ZeroFilled => &[],
         }
     }
 }
 
+/// Splits an object's contents into pages. Trailing zeros aren't stored
+/// since the virtual size covers them, all-zero pages in between are marked
+/// zero-filled if the extender knows that. Pages with fixups are always
+/// stored, whatever's in them. With `compress`, pages are compressed where
+/// that makes them smaller.
+fn le_pages(data: &[u8], fixup_offsets: &[u64], zero_filled: bool, compress: bool) -> Vec<LEPage> {
+    let fixup_end = fixup_offsets.iter().map(|offset| *offset as usize + 4).max().unwrap_or(0);
+    let len = data.iter().rposition(|byte| *byte != 0).map_or(0, |pos| pos + 1).max(fixup_end).min(data.len());
+    (0..len.div_ceil(0x1000)).map(|page| {
+        let start = page * 0x1000;
+        let contents = &data[start..len.min(start + 0x1000)];
+        // A fixup at the end of a page reaches into the next one
+        let has_fixups = fixup_offsets.iter()
+            .any(|offset| (*offset as usize / 0x1000..=(*offset as usize + 3) / 0x1000).contains(&page));
+        if zero_filled && !has_fixups && contents.iter().all(|byte| *byte == 0) {
+            return LEPage::ZeroFilled
+        }
+        if compress {
+            let packed = exepack::compress(contents);
+            if packed.len() < contents.len() { return LEPage::Compressed(packed) }
+        }
+        LEPage::Legal(contents.to_vec())
+    }).collect()
+}
+
 struct LEHeader {
-    num_text_pages: u32,
-    num_data_pages: u32,
+    header_offset: u64,
+    /// Write LX rather than LE, the page map is all that differs here
+    lx: bool,
+    /// Write the fixup, loader section and per-page checksums
+    checksums: bool,
+    target_os: u16,
+    module_flags: u32,
+    objects: Vec<LEObject>,
+    initial_cs: u32,
+    initial_eip: u32,
+    initial_ss: u32,
+    initial_esp: u32,
     last_page_bytes: u32,
     fixup_page_offsets: Vec<u32>,
-    fixup_records: Vec<u8>
+    fixup_records: Vec<u8>,
+    import_modules: Vec<u8>,
+    num_import_modules: u32,
+    import_procedures: Vec<u8>,
+    resident_names: Vec<u8>,
+    entry_table: Vec<u8>,
+    nonresident_names: Vec<u8>,
+    /// VxD device ID and DDK version, 0 for anything else
+    device_id: u16,
+    ddk_version: u16,
+}
+
+/// LE object number of a merged section, objects are numbered from 1.
+fn object_number(objects: &[LEObject], section_name: &str) -> Option<u8> {
+    objects.iter().position(|obj| obj.name == section_name).map(|idx| idx as u8 + 1)
 }
 
-fn write_le_header(new_header: &LEHeader, le_stub: &mut std::fs::File) -> std::result::Result<u32, Box<dyn std::error::Error>> {
-    let le_header_offset = 0x80;
+/// Appends a length-prefixed name and its ordinal to a names table.
+fn push_name_entry(table: &mut Vec<u8>, name: &str, ordinal: u16) {
+    let name = &name.as_bytes()[..name.len().min(0xFF)];
+    table.push(name.len() as u8);
+    table.extend_from_slice(name);
+    table.extend_from_slice(&ordinal.to_le_bytes());
+}
+
+fn write_le_header(new_header: &LEHeader, le_stub: &mut std::fs::File) -> error::Result<u32> {
+    let le_header_offset = new_header.header_offset;
     let object_table_offset = 0xC4 + le_header_offset;
-    let page_table_offset = 0xF4 + le_header_offset;
+    let page_table_offset = object_table_offset + new_header.objects.len() as u64 * 0x18;
+    let num_pages: u32 = new_header.objects.iter().map(|obj| obj.pages.len() as u32).sum();
 
+    // Zeroed header, so everything not set below stays 0
+    le_stub.write_all_at(&[0u8; 0xC4], le_header_offset)?;
+    // Signature, byte order, word order (little-endian) 00h
+    le_stub.write_at(if new_header.lx { b"LX\0\0" } else { b"LE\0\0" }, le_header_offset)?;
+    // CPU type (i386), target OS 08h 0Ah
+    le_stub.write_at(&2u16.to_le_bytes(), le_header_offset + 0x08)?;
+    le_stub.write_at(&new_header.target_os.to_le_bytes(), le_header_offset + 0x0A)?;
+    // Module type flags 10h
+    le_stub.write_at(&new_header.module_flags.to_le_bytes(), le_header_offset + 0x10)?;
     // Number of memory pages 14h
-    le_stub.write_at(&(new_header.num_text_pages + new_header.num_data_pages).to_le_bytes(), le_header_offset + 0x14)?;
-    // Object Table 1 page map index
-    le_stub.write_at(&(1 as u32).to_le_bytes(), object_table_offset + 0xC)?;
-    // Object Table 1 page map entries
-    le_stub.write_at(&new_header.num_text_pages.to_le_bytes(), object_table_offset + 0x10)?;
-    // Object Table 2 page map index
-    le_stub.write_at(&(new_header.num_text_pages + 1).to_le_bytes(), object_table_offset + 0x18 + 0xC)?;
-    // Object Table 2 page map entries
-    le_stub.write_at(&new_header.num_data_pages.to_le_bytes(), object_table_offset + 0x18 + 0x10)?;
+    le_stub.write_at(&num_pages.to_le_bytes(), le_header_offset + 0x14)?;
+    // Initial CS object, EIP, SS object, ESP 18h 1Ch 20h 24h
+    le_stub.write_at(&new_header.initial_cs.to_le_bytes(), le_header_offset + 0x18)?;
+    le_stub.write_at(&new_header.initial_eip.to_le_bytes(), le_header_offset + 0x1C)?;
+    le_stub.write_at(&new_header.initial_ss.to_le_bytes(), le_header_offset + 0x20)?;
+    le_stub.write_at(&new_header.initial_esp.to_le_bytes(), le_header_offset + 0x24)?;
+    // Memory page size 28h
+    le_stub.write_at(&0x1000u32.to_le_bytes(), le_header_offset + 0x28)?;
+    // Bytes on last page 2Ch, for LX the page offset shift, none
+    if !new_header.lx {
+        le_stub.write_at(&new_header.last_page_bytes.to_le_bytes(), le_header_offset + 0x2C)?;
+    }
+    // Object table offset, entries 40h 44h
+    le_stub.write_at(&0xC4u32.to_le_bytes(), le_header_offset + 0x40)?;
+    le_stub.write_at(&(new_header.objects.len() as u32).to_le_bytes(), le_header_offset + 0x44)?;
+    // Object page map offset 48h
+    le_stub.write_at(&((page_table_offset - le_header_offset) as u32).to_le_bytes(), le_header_offset + 0x48)?;
+    // Windows VxD device ID, DDK version B8h BAh
+    le_stub.write_at(&new_header.device_id.to_le_bytes(), le_header_offset + 0xB8)?;
+    le_stub.write_at(&new_header.ddk_version.to_le_bytes(), le_header_offset + 0xBA)?;
+
+    // Object Table
+    let mut page_map_index = 1u32;
+    for (idx, obj) in new_header.objects.iter().enumerate() {
+        let entry_offset = object_table_offset + idx as u64 * 0x18;
+        le_stub.write_at(&obj.virtual_size.to_le_bytes(), entry_offset)?;
+        le_stub.write_at(&obj.base.to_le_bytes(), entry_offset + 0x4)?;
+        le_stub.write_at(&obj.flags.to_le_bytes(), entry_offset + 0x8)?;
+        // Page map index
+        le_stub.write_at(&page_map_index.to_le_bytes(), entry_offset + 0xC)?;
+        // Page map entries
+        le_stub.write_at(&(obj.pages.len() as u32).to_le_bytes(), entry_offset + 0x10)?;
+        le_stub.write_at(&0u32.to_le_bytes(), entry_offset + 0x14)?;
+        page_map_index += obj.pages.len() as u32;
+    }
 
     // Page Table
     le_stub.seek(std::io::SeekFrom::Start(page_table_offset))?;
-    for p_idx in 1..(new_header.num_text_pages + new_header.num_data_pages + 1) {
-        le_stub.write(&((p_idx as u32) << 8).to_be_bytes())?;
+    // Page numbers (LE) or offsets (LX) count the stored pages only
+    let mut page_number = 0u32;
+    let mut page_data_offset = 0u32;
+    for page in new_header.objects.iter().flat_map(|obj| obj.pages.iter()) {
+        let stored = page.stored();
+        if new_header.lx {
+            le_stub.write_all(&page_data_offset.to_le_bytes())?;
+            le_stub.write_all(&(stored.len() as u16).to_le_bytes())?;
+            le_stub.write_all(&(page.flags() as u16).to_le_bytes())?;
+            page_data_offset += stored.len() as u32;
+        } else {
+            let number = if stored.is_empty() { 0 } else { page_number += 1; page_number };
+            le_stub.write_all(&(number << 8 | page.flags() as u32).to_be_bytes())?;
+        }
     }
     // Resident Name Table
     let name_table_offset = le_stub.stream_position()?;
-    le_stub.write(b"\x05ELFLE\0\0")?;
+    le_stub.write_all(&new_header.resident_names)?;
     // Entry Table
-    le_stub.write(&[0u8, 0u8])?;
+    let entry_table_offset = le_stub.stream_position()?;
+    if new_header.entry_table.is_empty() {
+        le_stub.write_all(&[0u8])?;
+    } else {
+        le_stub.write_all(&new_header.entry_table)?;
+    }
+    // Per-page checksum table 7Ch, still part of the loader section
+    if new_header.checksums {
+        let page_checksums_offset = le_stub.stream_position()?;
+        for page in new_header.objects.iter().flat_map(|obj| obj.pages.iter()) {
+            le_stub.write_all(&checksum::checksum(page.stored()).to_le_bytes())?;
+        }
+        le_stub.write_at(&((page_checksums_offset - le_header_offset) as u32).to_le_bytes(), le_header_offset + 0x7C)?;
+    }
 
     // Fixup page table
     let fixup_page_table_offset = le_stub.stream_position()?;
     for offset in new_header.fixup_page_offsets.iter() {
-        le_stub.write(&offset.to_le_bytes())?;
+        le_stub.write_all(&offset.to_le_bytes())?;
     }
     // Fixup records table
     let fixup_record_table_offset = le_stub.stream_position()?;
-    le_stub.write(&new_header.fixup_records)?;
+    le_stub.write_all(&new_header.fixup_records)?;
+    // Import module name table, import procedure name table
+    let import_module_table_offset = le_stub.stream_position()?;
+    le_stub.write_all(&new_header.import_modules)?;
+    let import_procedure_table_offset = le_stub.stream_position()?;
+    le_stub.write_all(&new_header.import_procedures)?;
 
-    // Fixup Section length 30h
-    le_stub.write_at(
-        &(new_header.fixup_page_offsets.len() as u32 * 4 + new_header.fixup_records.len() as u32).to_le_bytes(),
-        le_header_offset + 0x30
-    )?;
+    // Fixup Section length 30h, fixup page table up to the end of the import tables
+    let fixup_section_end = le_stub.stream_position()?;
+    le_stub.write_at(&((fixup_section_end - fixup_page_table_offset) as u32).to_le_bytes(), le_header_offset + 0x30)?;
+    // Loader Section length 38h, object table up to the fixup section
+    le_stub.write_at(&((fixup_page_table_offset - object_table_offset) as u32).to_le_bytes(), le_header_offset + 0x38)?;
     // Resource table offset, Resource table entries, Resident name table offset 50h 54h 58h
-    le_stub.write_at(&(name_table_offset as u32).to_le_bytes(), le_header_offset + 0x50)?;
+    le_stub.write_at(&(name_table_offset as u32 - le_header_offset as u32).to_le_bytes(), le_header_offset + 0x50)?;
     le_stub.write_at(&[0u8, 0u8, 0u8, 0u8], le_header_offset + 0x54)?;
-    le_stub.write_at(&(name_table_offset as u32).to_le_bytes(), le_header_offset + 0x58)?;
+    le_stub.write_at(&(name_table_offset as u32 - le_header_offset as u32).to_le_bytes(), le_header_offset + 0x58)?;
     // Entry table offset 5C
-    le_stub.write_at(&(name_table_offset as u32 + 8).to_le_bytes(), le_header_offset + 0x5C)?;
+    le_stub.write_at(&(entry_table_offset as u32 - le_header_offset as u32).to_le_bytes(), le_header_offset + 0x5C)?;
     // Fixup page table offset 68h
     le_stub.write_at(&(fixup_page_table_offset as u32 - le_header_offset as u32).to_le_bytes(), le_header_offset + 0x68)?;
     // Fixup record table offset 6Ch
     le_stub.write_at(&(fixup_record_table_offset as u32 - le_header_offset as u32).to_le_bytes(), le_header_offset + 0x6C)?;
+    // Import module table offset, entries, import procedure table offset 70h 74h 78h
+    le_stub.write_at(&(import_module_table_offset as u32 - le_header_offset as u32).to_le_bytes(), le_header_offset + 0x70)?;
+    le_stub.write_at(&new_header.num_import_modules.to_le_bytes(), le_header_offset + 0x74)?;
+    le_stub.write_at(&(import_procedure_table_offset as u32 - le_header_offset as u32).to_le_bytes(), le_header_offset + 0x78)?;
     // Data pages offset 80h
     //let data_pages_offset = 0x1000 +
     //    ((new_header.fixup_records.len() + new_header.fixup_page_offsets.len() * 4 + 0x68 + le_header_offset as usize) / 0x1000) * 0x1000;
@@ -149,182 +298,700 @@ fn write_le_header(new_header: &LEHeader, le_stub: &mut std::fs::File) -> std::r
     let data_pages_offset = le_stub.stream_position()?;
     le_stub.write_at(&(data_pages_offset as u32).to_le_bytes(), le_header_offset + 0x80)?;
 
+    // Fixup section checksum 34h, loader section checksum 3Ch, last since
+    // they cover everything above
+    if new_header.checksums {
+        let mut fixup_section = vec![0u8; (fixup_section_end - fixup_page_table_offset) as usize];
+        le_stub.read_exact_at(&mut fixup_section, fixup_page_table_offset)?;
+        le_stub.write_at(&checksum::checksum(&fixup_section).to_le_bytes(), le_header_offset + 0x34)?;
+        let mut loader_section = vec![0u8; (fixup_page_table_offset - object_table_offset) as usize];
+        le_stub.read_exact_at(&mut loader_section, object_table_offset)?;
+        le_stub.write_at(&checksum::checksum(&loader_section).to_le_bytes(), le_header_offset + 0x3C)?;
+    }
+
     Ok(data_pages_offset as u32)
 }
 
-fn output_le_relocations(obj_file: &object::File, le_header: &mut LEHeader, verbose: bool) {
-    let mut current_page = 0;
-    let mut reloc_idx = 0;
-    // start with 0
-    le_header.fixup_page_offsets.push(0x00000000);
-    let current_section = obj_file.section_by_name(".text").unwrap();
-    let mut text_relocations: Vec<(u64, Relocation)> = current_section.relocations().collect();
-    text_relocations.sort_by(|(loca, _), (locb, _)| loca.cmp(locb));
-    if verbose { println!("\t[LE Text Relocations]"); }
-    for (loc, rel) in text_relocations {
-        match rel.target() {
-            object::RelocationTarget::Symbol(s) => {
-                let sym = obj_file.symbol_by_index(s).unwrap();
-                let sec_index = obj_file.section_by_index(sym.section_index().unwrap()).unwrap();
-                let sec = sec_index.name().unwrap();
-                // Type
-                le_header.fixup_records.push(match rel.kind() {
-                    object::RelocationKind::Absolute => 0x07,
-                    object::RelocationKind::Relative => 0x08,
-                    object::RelocationKind::PltRelative => 0x08,
-                    _ => panic!()
-                });
-                // Flags
-                let dwordoffset = sym.address() >= 0x10000;
-                if dwordoffset {
-                    // 32-bit Target Offset Flag
-                    le_header.fixup_records.push(0x10);
-                } else {
-                    le_header.fixup_records.push(0);
-                }
-                let page = loc / 0x1000;
-                let src_in_page = loc % 0x1000;
-                // Source Offset in Page
-                le_header.fixup_records.extend_from_slice(&(src_in_page as u16).to_le_bytes());
-                // Target Object
-                let target_sec = match sec {
-                    ".text" => 1,
-                    ".data" => 2,
-                    _ => panic!()
-                };
-                le_header.fixup_records.push(target_sec);
-                // Target Offset
-                let target_offset = sym.address() as u32 +
-                    if rel.has_implicit_addend() & (rel.kind() == object::RelocationKind::Absolute) {
-                        let mut arr: [u8; 4] = [0; 4];
-                        arr.copy_from_slice(&current_section.data().unwrap()[loc as usize..loc as usize+4]);
-                        u32::from_le_bytes(arr)
-                    } else { 0 };
-                if dwordoffset {
-                    // 32-bit offset
-                    le_header.fixup_records.extend_from_slice(&(target_offset as u32).to_le_bytes());
-                } else {
-                    // 16-bit offset
-                    le_header.fixup_records.extend_from_slice(&(target_offset as u16).to_le_bytes());
-                }
+/// Writes the non-resident names table at `offset` (from top of file) and
+/// points the header at it. Nothing is written if there are no exports.
+fn write_le_nonresident_names(new_header: &LEHeader, le_stub: &mut std::fs::File, offset: u64) -> error::Result<()> {
+    if new_header.nonresident_names.is_empty() { return Ok(()) }
+    le_stub.write_all_at(&new_header.nonresident_names, offset)?;
+    // Non-resident names table offset, length 88h 8Ch
+    le_stub.write_at(&(offset as u32).to_le_bytes(), new_header.header_offset + 0x88)?;
+    le_stub.write_at(&(new_header.nonresident_names.len() as u32).to_le_bytes(), new_header.header_offset + 0x8C)?;
+    Ok(())
+}
 
+/// Appends debug info at `offset` (from top of file) and points the header at it.
+fn write_le_debug_info(new_header: &LEHeader, debug_info: &[u8], le_stub: &mut std::fs::File, offset: u64) -> error::Result<()> {
+    le_stub.write_all_at(debug_info, offset)?;
+    // Debug information offset, length 98h 9Ch
+    le_stub.write_at(&(offset as u32).to_le_bytes(), new_header.header_offset + 0x98)?;
+    le_stub.write_at(&(debug_info.len() as u32).to_le_bytes(), new_header.header_offset + 0x9C)?;
+    Ok(())
+}
 
-                while page > current_page {
-                    // new page
-                    le_header.fixup_page_offsets.push(reloc_idx);
-                    current_page += 1;
+/// Function and data symbols of the merged object, for the debug outputs.
+/// They're the input symbols `new_sym_map` maps, the anchors of the input
+/// sections are symbols of their own, so they don't come up.
+fn collect_debug_symbols(obj_file: &object::File, new_elf: &object::write::Object, new_secs: &[object::write::SectionId], new_sym_map: &std::collections::HashMap<SymbolIndex, object::write::SymbolId>) -> Vec<debug::DebugSymbol> {
+    let mut symbols = Vec::new();
+    for new in obj_file.symbols().filter_map(|symbol| new_sym_map.get(&symbol.index())) {
+        let symbol = new_elf.symbol(*new);
+        let code = match symbol.kind {
+            object::SymbolKind::Text => true,
+            object::SymbolKind::Data => false,
+            _ => continue
+        };
+        // Imports have no section
+        let object = match symbol.section.id().and_then(|id| new_secs.iter().position(|sec| *sec == id)) {
+            Some(idx) => idx as u8 + 1,
+            None => continue
+        };
+        symbols.push(debug::DebugSymbol {
+            name: String::from_utf8_lossy(&symbol.name).into_owned(),
+            object,
+            offset: symbol.value,
+            size: symbol.size,
+            code,
+            global: symbol.scope != object::SymbolScope::Compilation,
+        });
+    }
+    symbols
+}
 
-                    if verbose { println!("\nFixup page rollover at {:05x} for page {}", reloc_idx, current_page); }
-                }
-                if verbose { print!("{}:0x{:05x}->{}:0x{:05x} ", current_page, loc, target_sec, target_offset); }
-                if dwordoffset {
-                    // 32-bit offset
-                    reloc_idx += 2;
-                }
+/// Collects what the Watcom debugger needs from the input's DWARF and the
+/// merged object's symbols.
+fn output_watcom_debug(obj_file: &object::File, new_obj: &object::File, le_header: &LEHeader, placements: &debug::Placements, globals: &[debug::DebugSymbol], verbose: bool) -> error::Result<Vec<u8>> {
+    let object_sizes: Vec<u32> = le_header.objects.iter()
+        .map(|obj| object_section(new_obj, &obj.name).map(|sec| sec.size() as u32))
+        .collect::<error::Result<_>>()?;
+    // The object the program starts in, for DLLs the first code one
+    let code_object = match le_header.initial_cs {
+        0 => le_header.objects.iter().position(|obj| obj.code).map_or(1, |idx| idx as u8 + 1),
+        cs => cs as u8
+    };
+    let modules = debug::read_dwarf_modules(obj_file, placements, code_object, &object_sizes)?;
+    if verbose {
+        println!("\t[Watcom Debug Info]");
+        println!("lines in object {}", code_object);
+        for module in modules.iter() {
+            println!("module {}: {} lines", module.name, module.lines.len());
+            for (offset, line) in module.lines.iter() {
+                print!("{}@0x{:05x} ", line, offset);
             }
-            _ => eprintln!("Unsupported Relocation")
+            println!();
         }
-        reloc_idx += 7;
+        println!("{} globals", globals.len());
     }
-    while current_page < le_header.num_text_pages as u64 {
-        le_header.fixup_page_offsets.push(reloc_idx);
-        current_page += 1;
+    Ok(watcom::build_debug_info(&modules, globals, &object_sizes, code_object as u16))
+}
+
+/// Builds an ELF holding the input's DWARF relocated to LE addresses, that is
+/// each object's load base plus the offset inside it. The base comes from
+/// `--debug-base`, or the object table's relocation base address. With
+/// `with_contents` the objects are included too, relocated to those bases.
+fn output_debug_elf(obj_file: &object::File, new_obj: &object::File, le_header: &LEHeader, placements: &debug::Placements, symbols: &[debug::DebugSymbol], options: &Options, with_contents: bool) -> error::Result<Vec<u8>> {
+    let mut bases: Vec<u64> = le_header.objects.iter().map(|obj| obj.base as u64).collect();
+    for (obj, base) in options.debug_bases.iter() {
+        if *obj == 0 || *obj > bases.len() {
+            return Err(Error::MissingObject { name: obj.to_string(), wanted_by: String::from("--debug-base") });
+        }
+        bases[obj - 1] = *base;
     }
-    if verbose { println!(); }
-    current_page = 0;
-    let current_section = obj_file.section_by_name(".data").unwrap();
-    let mut data_relocations: Vec<(u64, Relocation)> = current_section.relocations().collect();
-    data_relocations.sort_by(|(loca, _), (locb, _)| loca.cmp(locb));
-    if verbose { println!("\t[LE Data Relocations]"); }
-    for (loc, rel) in data_relocations {
-        match rel.target() {
-            object::RelocationTarget::Symbol(s) => {
-                let sym = obj_file.symbol_by_index(s).unwrap();
-                let sec_index = obj_file.section_by_index(sym.section_index().unwrap()).unwrap();
-                let sec = sec_index.name().unwrap();
-                // Type
-                le_header.fixup_records.push(match rel.kind() {
-                    object::RelocationKind::Absolute => 0x07,
-                    object::RelocationKind::Relative => 0x08,
-                    object::RelocationKind::PltRelative => 0x08,
-                    _ => panic!()
-                });
-                // Flags
-                let dwordoffset = sym.address() >= 0x10000;
-                if dwordoffset {
-                    // 32-bit Target Offset Flag
-                    le_header.fixup_records.push(0x10);
-                } else {
-                    le_header.fixup_records.push(0);
-                }
-                let page = loc / 0x1000;
-                let src_in_page = loc % 0x1000;
-                // Source Offset in Page
-                le_header.fixup_records.extend_from_slice(&(src_in_page as u16).to_le_bytes());
-                // Target Object
-                let target_sec = match sec {
-                    ".text" => 1,
-                    ".data" => 2,
-                    _ => panic!()
-                };
-                le_header.fixup_records.push(target_sec);
-                // Target Offset
-                let target_offset = sym.address() as u32 +
-                    if rel.has_implicit_addend() & (rel.kind() == object::RelocationKind::Absolute) {
-                        let mut arr: [u8; 4] = [0; 4];
-                        arr.copy_from_slice(&current_section.data().unwrap()[loc as usize..loc as usize+4]);
-                        u32::from_le_bytes(arr)
-                    } else { 0 };
-                if dwordoffset {
-                    // 32-bit offset
-                    le_header.fixup_records.extend_from_slice(&(target_offset as u32).to_le_bytes());
-                } else {
-                    // 16-bit offset
-                    le_header.fixup_records.extend_from_slice(&(target_offset as u16).to_le_bytes());
-                }
 
+    let sections = le_header.objects.iter()
+        .map(|obj| object_section(new_obj, &obj.name))
+        .collect::<error::Result<Vec<_>>>()?;
+    // The merged sections map straight onto the LE objects
+    let mut new_placements = debug::Placements::new();
+    for (idx, sec) in sections.iter().enumerate() {
+        new_placements.insert(sec.index(), (idx as u8 + 1, 0));
+    }
+    let objects: Vec<debug::DebugObject> = le_header.objects.iter().zip(sections.iter()).zip(bases.iter())
+        .map(|((obj, sec), base)| debug::DebugObject {
+            name: &obj.name,
+            base: *base,
+            size: sec.size(),
+            code: obj.code,
+            data: if with_contents { Some(debug::relocate_section(new_obj, sec, &new_placements, &bases, *base)) } else { None },
+        })
+        .collect();
+    // CS:EIP, DLLs have no entry point
+    let entry = match le_header.initial_cs {
+        0 => 0,
+        cs => bases[cs as usize - 1] + le_header.initial_eip as u64
+    };
+    if options.verbose {
+        println!("\t[Debug ELF]");
+        for (idx, obj) in objects.iter().enumerate() {
+            println!("object {} {} at 0x{:08x}", idx + 1, obj.name, obj.base);
+        }
+    }
+    Ok(debug::build_debug_elf(obj_file, placements, &objects, symbols, entry, options.verbose)?)
+}
+
+/// Fills the entry table and non-resident names table with the global
+/// function and data symbols of the merged object. Ordinals are handed out
+/// in object/offset order so each object's entries pack into few bundles.
+fn output_le_exports(obj_file: &object::File, le_header: &mut LEHeader, verbose: bool) -> error::Result<()> {
+    let mut exports: Vec<(u8, u32, &str)> = Vec::new();
+    for sym in obj_file.symbols() {
+        if !sym.is_global() || sym.is_undefined() { continue }
+        match sym.kind() {
+            object::SymbolKind::Text | object::SymbolKind::Data => {},
+            _ => continue
+        }
+        let sec = match sym.section_index() {
+            Some(idx) => obj_file.section_by_index(idx)?,
+            None => continue
+        };
+        let target_obj = match object_number(&le_header.objects, sec.name()?) {
+            Some(object) => object,
+            None => continue
+        };
+        exports.push((target_obj, sym.address() as u32, sym.name()?));
+    }
+    exports.sort();
 
-                while page > current_page {
-                    // new page
-                    le_header.fixup_page_offsets.push(reloc_idx);
-                    current_page += 1;
-                    if verbose { println!("\nFixup page rollover at {:05x} for page {}", reloc_idx, current_page); }
-                }
-                if verbose { print!("{}:0x{:05x}->{}:0x{:05x} ", current_page, loc, target_sec, target_offset); }
-                if dwordoffset {
-                    // 32-bit offset
-                    reloc_idx += 2;
+    if verbose { println!("\t[LE Exports]"); }
+    // Non-resident names start with the module description, ordinal 0
+    push_name_entry(&mut le_header.nonresident_names, "ELFLE", 0);
+    for (idx, (target_obj, offset, name)) in exports.iter().enumerate() {
+        let ordinal = idx as u16 + 1;
+        push_name_entry(&mut le_header.nonresident_names, name, ordinal);
+        if verbose { println!("@{} {}:0x{:05x} {}", ordinal, target_obj, offset, name); }
+    }
+    le_header.nonresident_names.push(0);
+
+    let entries: Vec<LEEntry> = exports.iter().enumerate()
+        .map(|(idx, (object, offset, _))| LEEntry { ordinal: idx as u16 + 1, object: *object, offset: *offset, flags: 0x01 })
+        .collect();
+    le_header.entry_table = build_entry_table(&entries);
+    Ok(())
+}
+
+/// An entry point, by ordinal.
+struct LEEntry {
+    ordinal: u16,
+    object: u8,
+    offset: u32,
+    /// 0x01 exported, 0x02 shared data
+    flags: u8,
+}
+
+/// Builds the entry table from entries sorted by ordinal. Runs of ordinals in
+/// one object share a bundle, ordinals nothing uses get empty bundles.
+fn build_entry_table(entries: &[LEEntry]) -> Vec<u8> {
+    let mut table = Vec::new();
+    let mut next_ordinal = 1u32;
+    for run in entries.chunk_by(|a, b| a.object == b.object && a.ordinal as u32 + 1 == b.ordinal as u32) {
+        // Skipped ordinals: count, type 0 (unused)
+        let mut gap = run[0].ordinal as u32 - next_ordinal;
+        while gap > 0 {
+            let count = gap.min(0xFF);
+            table.push(count as u8);
+            table.push(0x00);
+            gap -= count;
+        }
+        // Entry bundles: count, type 3 (32-bit entry), object, then flags+offset per entry
+        for chunk in run.chunks(0xFF) {
+            table.push(chunk.len() as u8);
+            table.push(0x03);
+            table.extend_from_slice(&(chunk[0].object as u16).to_le_bytes());
+            for entry in chunk {
+                table.push(entry.flags);
+                table.extend_from_slice(&entry.offset.to_le_bytes());
+            }
+        }
+        next_ordinal = run[run.len() - 1].ordinal as u32 + 1;
+    }
+    table.push(0);
+    table
+}
+
+/// Looks up a defined symbol of the merged object, returning its LE object
+/// number and offset.
+fn find_symbol_entry(obj_file: &object::File, objects: &[LEObject], name: &str) -> error::Result<(u8, u32)> {
+    let sym = obj_file.symbols()
+        .find(|sym| !sym.is_undefined() && sym.name() == Ok(name))
+        .ok_or_else(|| Error::MissingSymbol { name: String::from(name), reference: None })?;
+    let sec = sym.section_index()
+        .and_then(|idx| obj_file.section_by_index(idx).ok())
+        .ok_or_else(|| Error::UnplacedSymbol { name: String::from(name) })?;
+    let object = object_number(objects, sec.name().unwrap_or(""))
+        .ok_or_else(|| Error::UnplacedSymbol { name: String::from(name) })?;
+    Ok((object, sym.address() as u32))
+}
+
+/// Fills the entry table and resident names table with the DLL's exports.
+fn output_dll_exports(obj_file: &object::File, le_header: &mut LEHeader, exports: &[def::Export], verbose: bool) -> error::Result<()> {
+    let ordinals = def::assign_ordinals(exports).map_err(Error::Invalid)?;
+    let mut entries = Vec::new();
+    if verbose { println!("\t[DLL Exports]"); }
+    for (export, ordinal) in exports.iter().zip(ordinals) {
+        let (object, offset) = find_symbol_entry(obj_file, &le_header.objects, &export.symbol)?;
+        if verbose { println!("@{} {}:0x{:05x} {}", ordinal, object, offset, export.name); }
+        if !export.noname {
+            push_name_entry(&mut le_header.resident_names, &export.name, ordinal);
+        }
+        entries.push(LEEntry { ordinal, object, offset, flags: 0x01 });
+    }
+    entries.sort_by_key(|entry| entry.ordinal);
+    le_header.entry_table = build_entry_table(&entries);
+    Ok(())
+}
+
+/// Exports the VxD's device descriptor block, `NAME_DDB`, as ordinal 1 with
+/// a resident name. The VMM finds the DDB through that entry.
+fn output_vxd_ddb(obj_file: &object::File, le_header: &mut LEHeader, vxd_name: &str, verbose: bool) -> error::Result<()> {
+    let ddb_name = format!("{}_DDB", vxd_name);
+    let (object, offset) = find_symbol_entry(obj_file, &le_header.objects, &ddb_name)?;
+    if verbose { println!("\t[VxD]\n@1 {}:0x{:05x} {}", object, offset, ddb_name); }
+
+    push_name_entry(&mut le_header.resident_names, &ddb_name, 1);
+    // Exported, shared data
+    le_header.entry_table = build_entry_table(&[LEEntry { ordinal: 1, object, offset, flags: 0x03 }]);
+    Ok(())
+}
+
+/// What a fixup points at.
+#[derive(Clone, Copy, PartialEq)]
+enum LETarget {
+    Internal { object: u8, offset: u32 },
+    /// `module` is a 1-based index into the import module name table
+    ImportOrdinal { module: u16, ordinal: u16 },
+    /// `name_offset` is into the import procedure name table
+    ImportName { module: u16, name_offset: u32 },
+}
+
+/// A fixup as the LE loader sees it, before it's encoded into a record.
+struct LEFixup {
+    /// 0x07 32-bit offset, 0x08 32-bit self-relative
+    source_type: u8,
+    source_offset: u16,
+    target: LETarget,
+    /// Added to an imported target's address
+    additive: u32,
+}
+
+/// Builds the import module and procedure name tables for the imports the
+/// merged object actually uses, and returns what each imported symbol's
+/// fixups point at.
+fn output_le_imports(obj_file: &object::File, le_header: &mut LEHeader, imports: &[def::Import], verbose: bool) -> std::collections::HashMap<String, LETarget> {
+    let mut targets = std::collections::HashMap::new();
+    let mut modules: Vec<&str> = Vec::new();
+    if verbose { println!("\t[LE Imports]"); }
+    for sym in obj_file.symbols() {
+        if !sym.is_undefined() { continue }
+        let name = match sym.name() {
+            Ok(name) => name,
+            Err(_) => continue
+        };
+        let import = match imports.iter().find(|import| import.symbol == name) {
+            Some(import) => import,
+            None => continue
+        };
+        let module = match modules.iter().position(|module| *module == import.module) {
+            Some(idx) => idx as u16 + 1,
+            None => {
+                modules.push(&import.module);
+                le_header.import_modules.push(import.module.len() as u8);
+                le_header.import_modules.extend_from_slice(import.module.as_bytes());
+                modules.len() as u16
+            }
+        };
+        let target = match &import.entry {
+            def::ImportEntry::Ordinal(ordinal) => LETarget::ImportOrdinal { module, ordinal: *ordinal },
+            def::ImportEntry::Name(proc_name) => {
+                // Procedure name offsets can't be 0, so the table starts with an empty name
+                if le_header.import_procedures.is_empty() {
+                    le_header.import_procedures.push(0);
                 }
+                let name_offset = le_header.import_procedures.len() as u32;
+                le_header.import_procedures.push(proc_name.len() as u8);
+                le_header.import_procedures.extend_from_slice(proc_name.as_bytes());
+                LETarget::ImportName { module, name_offset }
+            }
+        };
+        if verbose {
+            match &import.entry {
+                def::ImportEntry::Ordinal(ordinal) => println!("{} = {}.@{}", name, import.module, ordinal),
+                def::ImportEntry::Name(proc_name) => println!("{} = {}.{}", name, import.module, proc_name),
             }
-            _ => eprintln!("Unsupported Relocation")
         }
-        reloc_idx += 7;
+        targets.insert(String::from(name), target);
     }
-    while current_page < le_header.num_data_pages as u64 {
-        le_header.fixup_page_offsets.push(reloc_idx);
-        current_page += 1;
+    le_header.num_import_modules = modules.len() as u32;
+    targets
+}
+
+/// Collects the fixups of one merged section, bucketed by page.
+fn collect_le_fixups(obj_file: &object::File, objects: &[LEObject], imports: &std::collections::HashMap<String, LETarget>, inputs: &InputMap, section_name: &str, num_pages: u32, verbose: bool) -> error::Result<Vec<Vec<LEFixup>>> {
+    let mut pages: Vec<Vec<LEFixup>> = (0..num_pages).map(|_| Vec::new()).collect();
+    let current_section = object_section(obj_file, section_name)?;
+    let current_object = object_number(objects, section_name).unwrap_or(0);
+    let mut relocations: Vec<(u64, Relocation)> = current_section.relocations().collect();
+    relocations.sort_by_key(|(loc, _)| *loc);
+    for (loc, rel) in relocations {
+        let unsupported = || {
+            let (file, section, offset) = inputs.locate(current_object, loc);
+            Error::UnsupportedRelocation { file, section, offset, kind: format!("{:?} of {} bits", rel.kind(), rel.size()) }
+        };
+        // The symbol, or for section targets, the start of the section
+        let (target_sec, target_address, sym) = match rel.target() {
+            object::RelocationTarget::Symbol(s) => {
+                let sym = obj_file.symbol_by_index(s)?;
+                (sym.section_index(), sym.address(), Some(sym))
+            }
+            object::RelocationTarget::Section(sec_index) => (Some(sec_index), 0, None),
+            _ => return Err(unsupported())
+        };
+        // Type, there's no 16-bit self-relative one
+        let source_type = match (rel.kind(), rel.size()) {
+            (object::RelocationKind::Absolute, 32) => 0x07,
+            (object::RelocationKind::Absolute, 16) => 0x05,
+            (object::RelocationKind::Relative, 32) => 0x08,
+            (object::RelocationKind::PltRelative, 32) => 0x08,
+            _ => return Err(unsupported())
+        };
+        let page = loc / 0x1000;
+        let src_in_page = loc % 0x1000;
+        // The addend is in the data. Self-relative fixups are relative to the
+        // end of the field, so the -4 that's there for that goes, the rest,
+        // e.g. from a call into the middle of a section, stays
+        let implicit_addend = if rel.has_implicit_addend() {
+            let bytes = current_section.data()?.get(loc as usize..loc as usize + rel.size() as usize / 8).ok_or_else(|| {
+                let (file, section, offset) = inputs.locate(current_object, loc);
+                Error::OffsetOverflow { file, section, offset }
+            })?;
+            let addend = match *bytes {
+                [low, high] => i16::from_le_bytes([low, high]) as u32,
+                _ => u32::from_le_bytes(bytes.try_into().unwrap_or_default()),
+            };
+            if source_type == 0x08 { addend.wrapping_add(4) } else { addend }
+        } else { 0 };
+        let target_name = match &sym {
+            Some(sym) => sym.name()?,
+            None => ""
+        };
+        let unplaced = |target: &str| {
+            let (file, section, offset) = inputs.locate(current_object, loc);
+            Error::UnplacedTarget { file, section, offset, target: String::from(target) }
+        };
+        let (target, additive) = match target_sec {
+            Some(sec_index) => {
+                let sec = obj_file.section_by_index(sec_index)?;
+                // Target Object
+                let object = object_number(objects, sec.name()?).ok_or_else(|| unplaced(sec.name().unwrap_or("")))?;
+                // Target Offset, addends can be negative
+                let offset = (target_address as u32).wrapping_add(implicit_addend);
+                (LETarget::Internal { object, offset }, 0)
+            },
+            None => match imports.get(target_name) {
+                Some(target) => (*target, implicit_addend),
+                None => return Err(unplaced(target_name))
+            }
+        };
+        if verbose {
+            match &target {
+                LETarget::Internal { object, offset } => print!("{}:0x{:05x}->{}:0x{:05x} ", page, loc, object, offset),
+                _ => print!("{}:0x{:05x}->{} ", page, loc, target_name),
+            }
+        }
+        pages[page as usize].push(LEFixup {
+            source_type,
+            source_offset: src_in_page as u16,
+            target,
+            additive,
+        });
     }
     if verbose { println!(); }
-    // End of Fixup page table
-    le_header.fixup_page_offsets.push(reloc_idx);
+    Ok(pages)
+}
+
+/// Appends one fixup record for `fixup`'s target, applied at `sources`.
+/// More than one source makes it a source list record.
+fn push_le_fixup_record(records: &mut Vec<u8>, fixup: &LEFixup, sources: &[u16], profile: &extender::Profile) {
+    let mut flags = 0u8;
+    let mut target = Vec::new();
+    let push_module = |target: &mut Vec<u8>, flags: &mut u8, module: u16| {
+        if module > 0xFF {
+            // 16-bit Module Ordinal Flag
+            *flags |= 0x40;
+            target.extend_from_slice(&module.to_le_bytes());
+        } else {
+            target.push(module as u8);
+        }
+    };
+    match fixup.target {
+        LETarget::Internal { object, offset } => {
+            // Target Object
+            target.push(object);
+            // Target Offset
+            if profile.offset32 || offset >= 0x10000 {
+                // 32-bit Target Offset Flag
+                flags |= 0x10;
+                target.extend_from_slice(&offset.to_le_bytes());
+            } else {
+                target.extend_from_slice(&(offset as u16).to_le_bytes());
+            }
+        },
+        LETarget::ImportOrdinal { module, ordinal } => {
+            // Import by ordinal
+            flags |= 0x01;
+            push_module(&mut target, &mut flags, module);
+            target.extend_from_slice(&ordinal.to_le_bytes());
+        },
+        LETarget::ImportName { module, name_offset } => {
+            // Import by name
+            flags |= 0x02;
+            push_module(&mut target, &mut flags, module);
+            // Procedure Name Offset
+            if profile.offset32 || name_offset >= 0x10000 {
+                flags |= 0x10;
+                target.extend_from_slice(&name_offset.to_le_bytes());
+            } else {
+                target.extend_from_slice(&(name_offset as u16).to_le_bytes());
+            }
+        },
+    }
+    if fixup.additive != 0 {
+        // Additive Fixup Flag, 32-bit Additive Flag
+        flags |= 0x24;
+        target.extend_from_slice(&fixup.additive.to_le_bytes());
+    }
+    // Type, 20h Source List Flag
+    records.push(if sources.len() > 1 { fixup.source_type | 0x20 } else { fixup.source_type });
+    records.push(flags);
+    if sources.len() > 1 {
+        // Source Count
+        records.push(sources.len() as u8);
+    } else {
+        // Source Offset in Page
+        records.extend_from_slice(&sources[0].to_le_bytes());
+    }
+    records.extend_from_slice(&target);
+    if sources.len() > 1 {
+        // Source Offset List
+        for source in sources {
+            records.extend_from_slice(&source.to_le_bytes());
+        }
+    }
+}
+
+/// Encodes one page's fixups into the fixup record table.
+fn encode_le_fixups(page: &[LEFixup], le_header: &mut LEHeader, profile: &extender::Profile) {
+    if !profile.source_lists {
+        for fixup in page {
+            push_le_fixup_record(&mut le_header.fixup_records, fixup, &[fixup.source_offset], profile);
+        }
+        return
+    }
+    // Fixups sharing type and target, in order of first appearance
+    let mut groups: Vec<(&LEFixup, Vec<u16>)> = Vec::new();
+    for fixup in page {
+        let group = groups.iter_mut().find(|(first, _)| {
+            first.source_type == fixup.source_type &&
+            first.target == fixup.target &&
+            first.additive == fixup.additive
+        });
+        match group {
+            Some((_, sources)) => sources.push(fixup.source_offset),
+            None => groups.push((fixup, vec![fixup.source_offset])),
+        }
+    }
+    for (fixup, sources) in groups {
+        for chunk in sources.chunks(0xFF) {
+            push_le_fixup_record(&mut le_header.fixup_records, fixup, chunk, profile);
+        }
+    }
+}
+
+fn output_le_relocations(obj_file: &object::File, le_header: &mut LEHeader, imports: &std::collections::HashMap<String, LETarget>, inputs: &InputMap, profile: &extender::Profile, verbose: bool) -> error::Result<()> {
+    // start with 0
+    le_header.fixup_page_offsets.push(0x00000000);
+    let mut fixups = Vec::new();
+    for obj in le_header.objects.iter() {
+        if verbose { println!("\t[LE {} Relocations]", obj.name); }
+        fixups.extend(collect_le_fixups(obj_file, &le_header.objects, imports, inputs, &obj.name, obj.pages.len() as u32, verbose)?);
+    }
+    for page in fixups.iter() {
+        encode_le_fixups(page, le_header, profile);
+        // End of this page's records, start of the next one's
+        le_header.fixup_page_offsets.push(le_header.fixup_records.len() as u32);
+    }
+
+    println!("{} bytes of relocations", le_header.fixup_records.len());
+    Ok(())
+}
+
+/// Finds where a spot in an LE object came from, for error messages.
+struct InputMap<'a> {
+    obj_file: &'a object::File<'a>,
+    placements: &'a debug::Placements,
+    origins: &'a merge::Origins,
+}
+
+impl InputMap<'_> {
+    /// The input file, input section and offset in it of `offset` in LE
+    /// object `object`.
+    fn locate(&self, object: u8, offset: u64) -> (String, String, u64) {
+        let placed = self.placements.iter()
+            .filter(|(_, (obj, start))| *obj == object && *start <= offset)
+            .filter_map(|(index, (_, start))| Some((self.obj_file.section_by_index(*index).ok()?, *start)))
+            .find(|(section, start)| offset < start + section.size().max(1));
+        match placed {
+            Some((section, start)) => (
+                self.origins.get(&section.index()).cloned().unwrap_or_else(|| String::from("input")),
+                String::from(section.name().unwrap_or("")),
+                offset - start,
+            ),
+            None => (String::from("output"), format!("object {}", object), offset)
+        }
+    }
+}
 
-    println!("{} bytes of relocations", reloc_idx);
+/// Where the sections picked by `matches` were placed, as (object index,
+/// start, end). They should all be in one object, `what` names them if not.
+fn section_bounds(obj_file: &object::File, placements: &debug::Placements, what: &str, matches: impl Fn(&object::Section) -> bool) -> Option<(usize, u64, u64)> {
+    let mut bounds: Option<(usize, u64, u64)> = None;
+    for section in obj_file.sections() {
+        if !matches(&section) { continue }
+        let (obj, offset) = match placements.get(&section.index()) {
+            Some((obj, offset)) => (*obj as usize - 1, *offset),
+            None => continue
+        };
+        let end = offset + section.size();
+        bounds = match bounds {
+            None => Some((obj, offset, end)),
+            Some((first_obj, start, last_end)) if first_obj == obj => Some((obj, start.min(offset), last_end.max(end))),
+            Some(bounds) => {
+                eprintln!("Warning: {} is split across LE objects, only object {} counts", what, bounds.0 + 1);
+                Some(bounds)
+            }
+        };
+    }
+    bounds
 }
 
-fn convert(data: &[u8], verbose: bool) -> std::result::Result<(), Box<dyn std::error::Error>> {
+/// Symbols defined for the input to refer to, as (name, object index,
+/// offset):
+///  - `__NAME_start`/`__NAME_end` around each object's contents, NAME being
+///    the object's name without leading dots
+///  - `_etext`/`etext` at the end of the first code object
+///  - `__bss_start`/`__bss_end` around the bss, `_edata`/`edata` where it
+///    starts and `_end`/`end` at the end of its object
+///  - the start and end of each constructor and destructor table
+///
+/// Without bss, its symbols go at the end of the first data object (or the
+/// first object if there's none), empty tables at its start.
+fn linker_symbols(obj_file: &object::File, objects: &[(&layout::ObjectSpec, Vec<SectionIndex>)], placements: &debug::Placements) -> Vec<(String, usize, u64)> {
+    let mut symbols = Vec::new();
+    let object_ends: Vec<u64> = objects.iter().map(|(_, sections)| {
+        sections.iter()
+            .filter_map(|index| Some(placements.get(index)?.1 + obj_file.section_by_index(*index).ok()?.size()))
+            .max()
+            .unwrap_or(0)
+    }).collect();
+    for (idx, (spec, _)) in objects.iter().enumerate() {
+        let name = spec.name.trim_start_matches('.');
+        symbols.push((format!("__{}_start", name), idx, 0));
+        symbols.push((format!("__{}_end", name), idx, object_ends[idx]));
+    }
+    if let Some(code_obj) = objects.iter().position(|(spec, _)| spec.code) {
+        symbols.push((String::from("_etext"), code_obj, object_ends[code_obj]));
+        symbols.push((String::from("etext"), code_obj, object_ends[code_obj]));
+    }
+
+    let data_obj = objects.iter().position(|(spec, _)| !spec.code).unwrap_or(0);
+    let (bss_obj, bss_start, bss_end) = section_bounds(obj_file, placements, "bss", |section| section.kind() == object::SectionKind::UninitializedData)
+        .unwrap_or((data_obj, object_ends[data_obj], object_ends[data_obj]));
+    symbols.push((String::from("__bss_start"), bss_obj, bss_start));
+    symbols.push((String::from("__bss_end"), bss_obj, bss_end));
+    symbols.push((String::from("_edata"), bss_obj, bss_start));
+    symbols.push((String::from("edata"), bss_obj, bss_start));
+    symbols.push((String::from("_end"), bss_obj, object_ends[bss_obj]));
+    symbols.push((String::from("end"), bss_obj, object_ends[bss_obj]));
+
+    for table in layout::TABLES {
+        let (obj, start, end) = section_bounds(obj_file, placements, table.unsorted, |section| {
+            let name = section.name().unwrap_or("");
+            layout::glob_match(table.sorted, name) || layout::glob_match(table.unsorted, name)
+        }).unwrap_or((data_obj, 0, 0));
+        symbols.push((String::from(table.start), obj, start));
+        symbols.push((String::from(table.end), obj, end));
+    }
+    symbols
+}
+
+/// Drops the sections `--gc-sections` finds unreachable from the entry point,
+/// the exports and `--keep` symbols.
+fn gc_sections(obj_file: &object::File, layout: &layout::Layout, assigned: &mut [Vec<SectionIndex>], options: &Options) -> error::Result<()> {
+    let mut roots = Vec::new();
+    // The entry point is wherever the start offset lands once the start
+    // object's sections are placed as below. Everything up to it stays too,
+    // or the offset would end up somewhere else
+    if let Some((start_object, start_offset)) = &layout.start {
+        if let Some(idx) = layout.objects.iter().position(|spec| spec.name == *start_object) {
+            let mut offset = 0u64;
+            for index in assigned[idx].iter() {
+                let section = obj_file.section_by_index(*index)?;
+                offset = offset.next_multiple_of(layout.objects[idx].align.max(section.align()));
+                if offset > *start_offset as u64 { break }
+                roots.push(*index);
+                offset += section.size();
+            }
+        }
+    }
+    let mut kept: Vec<&str> = options.keep.iter().map(String::as_str).collect();
+    kept.extend(options.exports.iter().map(|export| export.symbol.as_str()));
+    let ddb_name = options.vxd.as_ref().map(|vxd_name| format!("{}_DDB", vxd_name));
+    kept.extend(ddb_name.as_deref());
+    roots.extend(gc::symbol_sections(obj_file, &kept));
+    if options.export_symbols {
+        roots.extend(obj_file.symbols().filter(|sym| sym.is_global() && !sym.is_undefined()).filter_map(|sym| sym.section_index()));
+    }
+    if roots.is_empty() {
+        return Err(Error::NoGcRoots);
+    }
+
+    let live = gc::live_sections(obj_file, &roots);
+    let mut removed_sections = 0;
+    let mut removed_bytes = 0;
+    for sections in assigned.iter_mut() {
+        sections.retain(|index| {
+            if live.contains(index) { return true }
+            let (name, size) = match obj_file.section_by_index(*index) {
+                Ok(section) => (String::from(section.name().unwrap_or("")), section.size()),
+                Err(_) => (String::new(), 0)
+            };
+            if options.verbose { println!("gc: removing {} (0x{:x} bytes)", name, size); }
+            removed_sections += 1;
+            removed_bytes += size;
+            false
+        });
+    }
+    println!("Removed {} unreferenced sections, {} bytes.", removed_sections, removed_bytes);
+    Ok(())
+}
+
+fn convert(data: &[u8], origins: &merge::Origins, options: &Options) -> error::Result<()> {
+    let verbose = options.verbose;
     let obj_file = object::File::parse(data)?;
 
     if verbose {
         for section in obj_file.sections() {
-            println!("\tSECTION [{}]\tKIND {}", section.name().unwrap(), match section.kind() {
+            println!("\tSECTION [{}]\tKIND {}", section.name()?, match section.kind() {
                 object::SectionKind::Text => "text",
                 object::SectionKind::Data => "data",
-                object::SectionKind::ReadOnlyData => "rodata",
+                object::SectionKind::ReadOnlyData | object::SectionKind::ReadOnlyString => "rodata",
                 object::SectionKind::UninitializedData => "bss",
                 _ => "Other",
             });
-            println!("\tRELOCATIONS FOR [{}]", section.name().unwrap());
-            print_section_relocations(&section, &obj_file);
+            println!("\tRELOCATIONS FOR [{}]", section.name()?);
+            print_section_relocations(&section, &obj_file)?;
             println!();
         }
     }
@@ -336,11 +1003,11 @@ fn convert(data: &[u8], verbose: bool) -> std::result::Result<(), Box<dyn std::e
             None
         };
         let sym_sec_name = if let Some(sec) = &sym_sec {
-            sec.name().unwrap()
+            sec.name()?
         } else {
             "None"
         };
-        if verbose { println!("SYMBOL [{}]\tKIND {}\tSECTION {}", symbol.name().unwrap(), match symbol.kind() {
+        if verbose { println!("SYMBOL [{}]\tKIND {}\tSECTION {}", symbol.name()?, match symbol.kind() {
             object::SymbolKind::Text => "Func",
             object::SymbolKind::Data => "Data",
             object::SymbolKind::Section => "Section",
@@ -349,188 +1016,231 @@ fn convert(data: &[u8], verbose: bool) -> std::result::Result<(), Box<dyn std::e
         }, sym_sec_name); } 
     }
 
+    let mut layout = match &options.layout {
+        Some(layout_path) => layout::parse_layout(&read_text(layout_path)?)
+            .map_err(|message| Error::Layout { path: layout_path.clone(), message })?,
+        None if options.vxd.is_some() => layout::vxd_layout(),
+        None => layout::default_layout(options.extender),
+    };
+    if options.dll.is_some() {
+        // No initialization routine, and DLLs run on the caller's stack
+        layout.start = None;
+        layout.stack = None;
+    }
+    let mut assigned = layout::assign_sections(&obj_file, &layout);
+    if options.gc_sections {
+        gc_sections(&obj_file, &layout, &mut assigned, options)?;
+    }
+    // Objects nothing goes in are dropped, unless the layout needs them or
+    // the program starts or has its stack in them, e.g. code with no data
+    // still gets an empty data object for its stack
+    let needed = |spec: &layout::ObjectSpec| [&layout.start, &layout.stack].iter()
+        .any(|at| at.as_ref().is_some_and(|(name, _)| *name == spec.name));
+    // Compilers emit an empty .text even when there's no code
+    let is_empty = |sections: &[SectionIndex]| sections.iter()
+        .all(|index| obj_file.section_by_index(*index).map_or(true, |section| section.size() == 0));
+    let objects: Vec<(&layout::ObjectSpec, Vec<SectionIndex>)> = layout.objects.iter()
+        .zip(assigned)
+        .filter(|(spec, sections)| spec.keep_empty || needed(spec) || !is_empty(sections))
+        .collect();
+    if let Some((name, _)) = &layout.start {
+        if objects.iter().any(|(spec, sections)| spec.name == *name && is_empty(sections)) {
+            eprintln!("Warning: nothing went in start object {}, there's no code to run", name);
+        }
+    }
+    if objects.is_empty() {
+        return Err(Error::NoSections);
+    }
+
     let mut new_elf = object::write::Object::new(object::BinaryFormat::Elf, object::Architecture::I386, object::Endianness::Little);
-    let text_sec_name = Vec::from(".text");
-    let null_seg = Vec::new();
-    let new_text_sec = new_elf.add_section(null_seg, text_sec_name, object::SectionKind::Text);
-    let data_sec_name = Vec::from(".data");
-    let null_seg = Vec::new();
-    let new_data_sec = new_elf.add_section(null_seg, data_sec_name, object::SectionKind::Data);
+    let new_secs: Vec<_> = objects.iter().map(|(spec, _)| {
+        let kind = if spec.code { object::SectionKind::Text } else { object::SectionKind::Data };
+        new_elf.add_section(Vec::new(), Vec::from(spec.name.as_str()), kind)
+    }).collect();
 
+    // Input symbol -> output symbol, by index since names repeat: locals of
+    // different inputs, and the input sections, anchored by same-named symbols
     let mut new_sym_map = std::collections::HashMap::new();
-    // get start section first
-    if let Some(start_section) = obj_file.section_by_name(".text.start") {
-        let new_symbol = new_elf.add_symbol(object::write::Symbol {
-            name: Vec::from(".text.start"),
-            value: 0x0,
-            size: 0x0,
-            kind: object::SymbolKind::Text,
-            scope: object::SymbolScope::Compilation,
-            weak: false,
-            section: object::write::SymbolSection::Section(new_text_sec),
-            flags: object::SymbolFlags::None,
-        });
-        new_elf.add_symbol_data(new_symbol, new_text_sec, start_section.data().unwrap(), 1);
-        new_sym_map.insert(String::from(".text.start"), new_symbol);
-    }
-    for section in obj_file.sections() {
-        if section.name().unwrap() == ".text.start" { continue }
-        match section.kind() {
-            object::SectionKind::Text => {
-                let new_symbol = new_elf.add_symbol(object::write::Symbol {
-                    name: Vec::from(section.name().unwrap()),
-                    value: 0x0,
-                    size: 0x0,
-                    kind: object::SymbolKind::Text,
-                    scope: object::SymbolScope::Compilation,
-                    weak: false,
-                    section: object::write::SymbolSection::Section(new_text_sec),
-                    flags: object::SymbolFlags::None,
-                });
-                new_elf.add_symbol_data(new_symbol, new_text_sec, section.data().unwrap(), 1);
-                new_sym_map.insert(String::from(section.name().unwrap()), new_symbol);
-            },
-            object::SectionKind::Data | object::SectionKind::ReadOnlyData => {
-                let new_symbol = new_elf.add_symbol(object::write::Symbol {
-                    name: Vec::from(section.name().unwrap()),
-                    value: 0x0,
-                    size: 0x0,
-                    kind: object::SymbolKind::Data,
-                    scope: object::SymbolScope::Compilation,
-                    weak: false,
-                    section: object::write::SymbolSection::Section(new_data_sec),
-                    flags: object::SymbolFlags::None,
-                });
-                new_elf.add_symbol_data(new_symbol, new_data_sec, section.data().unwrap(), 1);
-                new_sym_map.insert(String::from(section.name().unwrap()), new_symbol);
-            },
-            object::SectionKind::UninitializedData => {
-                let new_symbol = new_elf.add_symbol(object::write::Symbol {
-                    name: Vec::from(section.name().unwrap()),
-                    value: 0x0,
-                    size: 0x0,
-                    kind: object::SymbolKind::Data,
-                    scope: object::SymbolScope::Compilation,
-                    weak: false,
-                    section: object::write::SymbolSection::Section(new_data_sec),
-                    flags: object::SymbolFlags::None,
-                });
+    let mut section_sym_map = std::collections::HashMap::new();
+    // input section -> (LE object, offset in object), for the debug info
+    let mut placements = debug::Placements::new();
+    for (obj_idx, (spec, sections)) in objects.iter().enumerate() {
+        let new_sec = new_secs[obj_idx];
+        for section_index in sections {
+            let section = obj_file.section_by_index(*section_index)?;
+            let new_symbol = new_elf.add_symbol(object::write::Symbol {
+                name: Vec::from(section.name()?),
+                value: 0x0,
+                size: 0x0,
+                kind: if spec.code { object::SymbolKind::Text } else { object::SymbolKind::Data },
+                scope: object::SymbolScope::Compilation,
+                weak: false,
+                section: object::write::SymbolSection::Section(new_sec),
+                flags: object::SymbolFlags::None,
+            });
+            // Sections keep their own alignment if the object's is less
+            let align = spec.align.max(section.align());
+            let offset = if section.kind() == object::SectionKind::UninitializedData {
                 let bss_zeros = vec![0; section.size() as usize];
-                new_elf.add_symbol_data(new_symbol, new_data_sec, &bss_zeros, 1);
-                new_sym_map.insert(String::from(section.name().unwrap()), new_symbol);
-            }
-            _ => {}
+                new_elf.add_symbol_data(new_symbol, new_sec, &bss_zeros, align)
+            } else {
+                new_elf.add_symbol_data(new_symbol, new_sec, section.data()?, align)
+            };
+            section_sym_map.insert(section.index(), new_symbol);
+            placements.insert(section.index(), (obj_idx as u8 + 1, offset));
         }
     }
+    // Symbols the input leaves for the linker to define
+    let linker_symbols = linker_symbols(&obj_file, &objects, &placements);
     for symbol in obj_file.symbols() {
-        let sym_sec_idx = symbol.section_index();
-        let sym_sec = if let Some(idx) = sym_sec_idx {
-            Some(obj_file.section_by_index(idx)?)
-        } else {
-            None
-        };
-        let new_offset = if let Some(sec) = &sym_sec {
-            let old_section_name = sec.name().unwrap();
-            match new_sym_map.get(old_section_name) {
-                Some(id) => new_elf.symbol(*id).value + symbol.address(),
-                None => symbol.address()
-            }
-        } else {
-            symbol.address()
-        };
-        match symbol.kind() {
-            object::SymbolKind::Text => {
+        if symbol.is_undefined() {
+            let linker_symbol = linker_symbols.iter().find(|(name, _, _)| Ok(name.as_str()) == symbol.name());
+            if let Some((name, obj, offset)) = linker_symbol {
+                if verbose { println!("defining {} at {}:0x{:05x}", name, obj + 1, offset); }
                 let new_symbol = new_elf.add_symbol(object::write::Symbol {
-                    name: Vec::from(symbol.name().unwrap()),
-                    value: new_offset,
-                    size: symbol.size(),
-                    kind: object::SymbolKind::Text,
-                    scope: object::SymbolScope::Compilation,
-                    weak: false,
-                    section: object::write::SymbolSection::Section(new_text_sec),
-                    flags: object::SymbolFlags::None,
-                });
-                new_sym_map.insert(String::from(symbol.name().unwrap()), new_symbol);
-            }
-            object::SymbolKind::Data => {
-                let new_symbol = new_elf.add_symbol(object::write::Symbol {
-                    name: Vec::from(symbol.name().unwrap()),
-                    value: new_offset,
-                    size: symbol.size(),
+                    name: Vec::from(name.as_str()),
+                    value: *offset,
+                    size: 0,
                     kind: object::SymbolKind::Data,
-                    scope: object::SymbolScope::Compilation,
+                    scope: object::SymbolScope::Linkage,
                     weak: false,
-                    section: object::write::SymbolSection::Section(new_data_sec),
+                    section: object::write::SymbolSection::Section(new_secs[*obj]),
                     flags: object::SymbolFlags::None,
                 });
-                new_sym_map.insert(String::from(symbol.name().unwrap()), new_symbol);
+                new_sym_map.insert(symbol.index(), new_symbol);
+                continue
             }
-            _ => {}
         }
+        // Imports stay undefined, the loader resolves them through fixups
+        if symbol.is_undefined() && options.imports.iter().any(|import| Ok(import.symbol.as_str()) == symbol.name()) {
+            let new_symbol = new_elf.add_symbol(object::write::Symbol {
+                name: Vec::from(symbol.name()?),
+                value: 0,
+                size: 0,
+                kind: symbol.kind(),
+                scope: object::SymbolScope::Linkage,
+                weak: symbol.is_weak(),
+                section: object::write::SymbolSection::Undefined,
+                flags: object::SymbolFlags::None,
+            });
+            new_sym_map.insert(symbol.index(), new_symbol);
+            continue
+        }
+        // Symbols in sections that weren't placed have nowhere to go
+        let (obj_idx, new_offset) = match symbol.section_index().and_then(|idx| placements.get(&idx)) {
+            Some((obj, offset)) => (*obj as usize - 1, offset + symbol.address()),
+            None => continue
+        };
+        // Whatever kind it is, it moves with its section
+        let kind = match symbol.kind() {
+            object::SymbolKind::Section | object::SymbolKind::File | object::SymbolKind::Null => continue,
+            kind => kind
+        };
+        let new_symbol = new_elf.add_symbol(object::write::Symbol {
+            name: Vec::from(symbol.name()?),
+            value: new_offset,
+            size: symbol.size(),
+            kind,
+            scope: symbol.scope(),
+            weak: symbol.is_weak(),
+            section: object::write::SymbolSection::Section(new_secs[obj_idx]),
+            flags: object::SymbolFlags::None,
+        });
+        new_sym_map.insert(symbol.index(), new_symbol);
     }
 
     for section in obj_file.sections() {
-        if section.kind() != object::SectionKind::Text &&
-           section.kind() != object::SectionKind::Data &&
-           section.kind() != object::SectionKind::ReadOnlyData &&
-           section.kind() != object::SectionKind::UninitializedData {
-                continue
-        }
-        let new_src_sym_id = new_sym_map.get(section.name().unwrap()).unwrap();
+        if !placements.contains_key(&section.index()) { continue }
+        let new_src_sym_id = match section_sym_map.get(&section.index()) {
+            Some(id) => id,
+            None => continue
+        };
         let base_addr = new_elf.symbol(*new_src_sym_id).value;
         for (src, reloc) in section.relocations() {
-            match reloc.target() {
+            // Section targets are the same as their section symbol
+            let (old_sym_name, old_sym_address, old_sec_index, new_sym) = match reloc.target() {
                 RelocationTarget::Symbol(sym_idx) => {
-                    let old_sym = obj_file.symbol_by_index(sym_idx).unwrap();
-                    // ???????
-                    if old_sym.section_index().is_none() { continue }
-                    let old_sec = obj_file.section_by_index(old_sym.section_index().unwrap()).unwrap();
-                    let new_sym = if let Some(new_sym) = new_sym_map.get(old_sym.name().unwrap()) {
-                        Some(new_sym)
-                    } else {
-                        if let Some(new_sym) = new_sym_map.get(old_sec.name().unwrap()) {
-                            Some(new_sym)
-                        } else {
-                            None
-                        }
+                    let old_sym = obj_file.symbol_by_index(sym_idx)?;
+                    let new_sym = new_sym_map.get(&sym_idx)
+                        .or_else(|| old_sym.section_index().and_then(|idx| section_sym_map.get(&idx)));
+                    (String::from(old_sym.name()?), old_sym.address(), old_sym.section_index(), new_sym)
+                }
+                RelocationTarget::Section(sec_idx) => {
+                    let old_sec = obj_file.section_by_index(sec_idx)?;
+                    (String::from(old_sec.name()?), 0, Some(sec_idx), section_sym_map.get(&sec_idx))
+                }
+                // No symbol at all, the value is all in the addend
+                _ => continue
+            };
+            let (old_sec_name, old_sec_address) = match old_sec_index {
+                Some(idx) => {
+                    let old_sec = obj_file.section_by_index(idx)?;
+                    (String::from(old_sec.name()?), old_sec.address())
+                },
+                // Imports and linker defined symbols
+                None if new_sym.is_some() => (String::from("undefined"), 0),
+                None => {
+                    let weak = match reloc.target() {
+                        RelocationTarget::Symbol(sym_idx) => obj_file.symbol_by_index(sym_idx)?.is_weak(),
+                        _ => false
                     };
-                    if let Some(new) = new_sym {
-                        let new_src_sec_id = new_elf.symbol(*new_src_sym_id).section.id().unwrap();
-                        new_elf.add_relocation(new_src_sec_id, object::write::Relocation {
-                            offset: base_addr + src,
-                            size: reloc.size(),
-                            kind: reloc.kind(),
-                            encoding: reloc.encoding(),
-                            symbol: *new,
-                            addend: reloc.addend(),
-                        })?;
-                        if verbose { println!("reloc {:04x} in {} -> {} in {} {:04x}@{:04x} Became {:04x} in {} -> {} in {} ({:04x})",
-                            src,
-                            section.name().unwrap(),
-                            old_sym.name().unwrap(),
-                            old_sec.name().unwrap(),
-                            old_sym.address(),
-                            old_sec.address(),
-                            base_addr + src,
-                            new_elf.section(new_src_sec_id).name().unwrap(),
-                            new_elf.symbol(*new).name().unwrap(),
-                            new_elf.section(new_elf.symbol(*new).section.id().unwrap()).name().unwrap(),
-                            new_elf.symbol(*new).value
-                        ); }
-                    } else {
-                        if verbose { eprintln!("Warning: Couldn't find new equivalent of {:04x} -> symbol {} in {} ({:04x}@{:04x})", src,
-                            old_sym.name().unwrap(),
-                            old_sec.name().unwrap(),
-                            old_sym.address(),
-                            old_sec.address()
-                        ); }
+                    let file = origins.get(&section.index()).cloned().unwrap_or_else(|| String::from("input"));
+                    // Weak references to something nothing defines are 0,
+                    // absolute ones have just their addend in the data
+                    // already. A relative one needs the distance to 0, which
+                    // no fixup gives
+                    if weak && reloc.kind() == object::RelocationKind::Absolute { continue }
+                    if weak {
+                        return Err(Error::UnsupportedRelocation {
+                            file,
+                            section: String::from(section.name()?),
+                            offset: src,
+                            kind: format!("{:?} to undefined weak {}", reloc.kind(), old_sym_name),
+                        })
                     }
-                },
-                _ => { panic!("Unsupported Relocation Type"); },
+                    return Err(Error::MissingSymbol {
+                        name: old_sym_name,
+                        reference: Some((file, String::from(section.name()?), src)),
+                    })
+                }
+            };
+            if let Some(new) = new_sym {
+                let new_src_sec_id = new_secs[placements[&section.index()].0 as usize - 1];
+                new_elf.add_relocation(new_src_sec_id, object::write::Relocation {
+                    offset: base_addr + src,
+                    size: reloc.size(),
+                    kind: reloc.kind(),
+                    encoding: reloc.encoding(),
+                    symbol: *new,
+                    addend: reloc.addend(),
+                })?;
+                if verbose { println!("reloc {:04x} in {} -> {} in {} {:04x}@{:04x} Became {:04x} in {} -> {} in {} ({:04x})",
+                    src,
+                    section.name()?,
+                    old_sym_name,
+                    old_sec_name,
+                    old_sym_address,
+                    old_sec_address,
+                    base_addr + src,
+                    new_elf.section(new_src_sec_id).name().unwrap_or(""),
+                    new_elf.symbol(*new).name().unwrap_or(""),
+                    new_elf.symbol(*new).section.id().map_or("import", |id| new_elf.section(id).name().unwrap_or("")),
+                    new_elf.symbol(*new).value
+                ); }
+            } else {
+                // The target's section wasn't placed, leaving this out would
+                // leave the code pointing at nothing
+                return Err(Error::UnplacedTarget {
+                    file: origins.get(&section.index()).cloned().unwrap_or_else(|| String::from("input")),
+                    section: String::from(section.name()?),
+                    offset: src,
+                    target: if old_sym_name.is_empty() { old_sec_name } else { old_sym_name },
+                })
             }
         }
     }
 
+    let debug_symbols = collect_debug_symbols(&obj_file, &new_elf, &new_secs, &new_sym_map);
     {
         let mut out_elf = fs::File::create("new.elf")?;
         new_elf.write_stream(&mut out_elf)?;
@@ -543,65 +1253,413 @@ fn convert(data: &[u8], verbose: bool) -> std::result::Result<(), Box<dyn std::e
     let new_obj = object::File::parse(&*new_file)?;
 
     if verbose { println!(); }
-    let text_len = new_obj.section_by_name(".text").unwrap().data().unwrap().len();
-    let data_len = new_obj.section_by_name(".data").unwrap().data().unwrap().len();
-    let text_pages = if text_len % 0x1000 == 0 {
-        text_len / 0x1000
-    } else {
-        (text_len / 0x1000) + 1
-    };
-    let data_pages = if data_len % 0x1000 == 0 {
-        data_len / 0x1000
-    } else {
-        (data_len / 0x1000) + 1
+    let mut le_objects = Vec::new();
+    // Only the very last page in the file can be short, every other page is
+    // padded out to the page size
+    let mut last_page_bytes = 0;
+    for (spec, _) in objects.iter() {
+        let sec = object_section(&new_obj, &spec.name)?;
+        let data = sec.data()?;
+        let fixup_offsets: Vec<u64> = sec.relocations().map(|(offset, _)| offset).collect();
+        let pages = le_pages(data, &fixup_offsets, options.lx || options.extender.zero_filled_pages, options.exepack);
+        let stored: usize = pages.iter().map(|page| page.stored().len()).sum();
+        println!("[{}] size: 0x{:08x} ({} pages, 0x{:x} bytes stored)", spec.name, data.len(), pages.len(), stored);
+        // An object's last page is always stored, zeros at the end aren't pages
+        if let Some(page) = pages.last() {
+            last_page_bytes = page.stored().len() as u32;
+        }
+        if verbose {
+            println!("\n\tRELOCATIONS FOR [{}]", spec.name);
+            print_section_relocations(&sec, &new_obj)?;
+        }
+        le_objects.push(LEObject {
+            name: spec.name.clone(),
+            virtual_size: spec.min_size.max(data.len() as u32),
+            base: spec.base,
+            flags: spec.flags,
+            code: spec.code,
+            pages,
+        });
+    }
+    // Initial CS:EIP and SS:ESP, as (object number, offset)
+    let resolve_start = |start: &Option<(String, u32)>, key: &str| -> error::Result<(u32, u32)> {
+        match start {
+            Some((name, offset)) => match object_number(&le_objects, name) {
+                Some(obj) => Ok((obj as u32, *offset)),
+                None => Err(Error::MissingObject { name: name.clone(), wanted_by: format!("layout {}", key) })
+            },
+            None => Ok((0, 0))
+        }
     };
-    println!("[.text] size: 0x{:08x} ({} pages)", text_len, text_pages);
-    println!("[.data] size: 0x{:08x} ({} pages)", data_len, data_pages);
+    let (initial_cs, initial_eip) = resolve_start(&layout.start, "start")?;
+    let (initial_ss, initial_esp) = resolve_start(&layout.stack, "stack")?;
 
-    if verbose {
-        let text_sec = new_obj.section_by_name(".text").unwrap();
-        println!("\n\tRELOCATIONS FOR [.text]");
-        print_section_relocations(&text_sec, &new_obj);
-        println!("\n\tRELOCATIONS FOR [.data]");
-        let data_sec = new_obj.section_by_name(".data").unwrap();
-        print_section_relocations(&data_sec, &new_obj);
-    }
-
-    let le_stub: Vec<u8> = Vec::from(*LE_STUB);
-    let mut out_file = fs::File::create("a.exe")?;
-    out_file.write(&le_stub)?;
-    out_file.set_len(0x2000)?;
+    let (mut le_stub, header_align) = match (&options.stub, &options.bind) {
+        (_, Some(extender_path)) => (stub::load_extender(read_file(extender_path)?)
+            .map_err(|message| Error::Stub { path: extender_path.clone(), message })?, options.extender.bind_align),
+        (Some(stub_path), None) => (stub::load_stub(read_file(stub_path)?)
+            .map_err(|message| Error::Stub { path: stub_path.clone(), message })?, 16),
+        (None, None) => (stub::build_stub(options.stub_message.as_deref().unwrap_or(stub::DEFAULT_MESSAGE))
+            .map_err(Error::Invalid)?, 16),
+    };
+    // Every offset "from top of file" is computed from here on, so they
+    // already account for however big the stub is
+    let header_offset = stub::place_le_header(&mut le_stub, header_align);
+    if verbose { println!("LE header at 0x{:04x}", header_offset); }
+    // Read back too, for the checksums
+    let mut out_file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open("a.exe")?;
+    out_file.write_all(&le_stub)?;
     let mut header = LEHeader {
-        num_text_pages: text_pages as u32,
-        num_data_pages: data_pages as u32,
-        last_page_bytes: 0x1000,
+        header_offset,
+        lx: options.lx,
+        checksums: options.checksums,
+        target_os: options.extender.target_os,
+        // Library module flag 8000h
+        module_flags: options.extender.module_flags | if options.dll.is_some() { 0x00008000 } else { 0 },
+        objects: le_objects,
+        initial_cs,
+        initial_eip,
+        initial_ss,
+        initial_esp,
+        last_page_bytes,
         fixup_page_offsets: Vec::new(),
         fixup_records: Vec::new(),
+        import_modules: Vec::new(),
+        num_import_modules: 0,
+        import_procedures: Vec::new(),
+        resident_names: Vec::new(),
+        entry_table: Vec::new(),
+        nonresident_names: Vec::new(),
+        device_id: options.vxd_device_id.unwrap_or(0),
+        // Windows 95 DDK unless told otherwise
+        ddk_version: if options.vxd.is_some() { options.vxd_ddk_version.unwrap_or(0x0400) } else { 0 },
     };
-    output_le_relocations(&new_obj, &mut header, verbose);
+    let imports = output_le_imports(&new_obj, &mut header, &options.imports, verbose);
+    let inputs = InputMap { obj_file: &obj_file, placements: &placements, origins };
+    output_le_relocations(&new_obj, &mut header, &imports, &inputs, options.extender, verbose)?;
+    // Resident names start with the module name, ordinal 0
+    let module_name = options.vxd.as_deref().or(options.dll.as_deref()).unwrap_or("ELFLE");
+    push_name_entry(&mut header.resident_names, module_name, 0);
+    if let Some(vxd_name) = &options.vxd {
+        output_vxd_ddb(&new_obj, &mut header, vxd_name, verbose)?;
+    }
+    if options.dll.is_some() {
+        output_dll_exports(&new_obj, &mut header, &options.exports, verbose)?;
+    }
+    header.resident_names.push(0);
+    if options.export_symbols {
+        output_le_exports(&new_obj, &mut header, verbose)?;
+    }
     let data_pages_offset = write_le_header(&header, &mut out_file)?;
     println!("Data Pages Offset: 0x{:04x}", data_pages_offset);
-    out_file.write_all_at(new_obj.section_by_name(".text").unwrap().data().unwrap(), data_pages_offset as u64)?;
-    let data_loc = (header.num_text_pages * 0x1000) + data_pages_offset;
-    out_file.write_all_at(new_obj.section_by_name(".data").unwrap().data().unwrap(), data_loc as u64)?;
+    // Each object's stored pages follow the previous object's
+    let mut page_offset = data_pages_offset as u64;
+    let mut file_len = page_offset as usize;
+    for page in header.objects.iter().flat_map(|obj| obj.pages.iter()) {
+        let stored = page.stored();
+        if stored.is_empty() { continue }
+        out_file.write_all_at(stored, page_offset)?;
+        file_len = page_offset as usize + stored.len();
+        // LE pages are a page apart, the gaps after short ones read back as
+        // zeros, that's the padding. LX pages can be any size.
+        page_offset += if header.lx { stored.len() as u64 } else { 0x1000 };
+    }
 
-    println!("Wrote a.exe, {} bytes.", data_loc as usize + new_obj.section_by_name(".data").unwrap().data().unwrap().len());
+    if !header.nonresident_names.is_empty() {
+        // Loaders only read last_page_bytes of the last page, so the names
+        // can follow right after it
+        let names_offset = file_len as u64;
+        write_le_nonresident_names(&header, &mut out_file, names_offset)?;
+        file_len = names_offset as usize + header.nonresident_names.len();
+    }
+
+    if let Some(debug_elf_path) = &options.debug_elf {
+        let debug_elf = output_debug_elf(&obj_file, &new_obj, &header, &placements, &debug_symbols, options, true)?;
+        fs::write(debug_elf_path, &debug_elf)?;
+        println!("Wrote {}, {} bytes.", debug_elf_path, debug_elf.len());
+    }
+    if options.keep_debug {
+        let debug_elf = output_debug_elf(&obj_file, &new_obj, &header, &placements, &debug_symbols, options, false)?;
+        write_le_debug_info(&header, &debug_elf, &mut out_file, file_len as u64)?;
+        file_len += debug_elf.len();
+        println!("{} bytes of DWARF debug info", debug_elf.len());
+    }
+    if options.watcom_debug {
+        // The debugger looks for the master header at the end of the file
+        let debug_info = output_watcom_debug(&obj_file, &new_obj, &header, &placements, &debug_symbols, verbose)?;
+        write_le_debug_info(&header, &debug_info, &mut out_file, file_len as u64)?;
+        file_len += debug_info.len();
+        println!("{} bytes of Watcom debug info", debug_info.len());
+    }
+
+    // Nothing past what's actually used, loaders may check the length
+    out_file.set_len(file_len as u64)?;
+    println!("Wrote a.exe, {} bytes.", file_len);
 
     Ok(())
 }
 
-fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
+struct Options {
+    verbose: bool,
+    export_symbols: bool,
+    watcom_debug: bool,
+    keep_debug: bool,
+    debug_elf: Option<String>,
+    debug_bases: Vec<(usize, u64)>,
+    stub: Option<String>,
+    stub_message: Option<String>,
+    bind: Option<String>,
+    extender: &'static extender::Profile,
+    /// Write LX instead of LE
+    lx: bool,
+    /// Compress LX pages with EXEPACK2
+    exepack: bool,
+    checksums: bool,
+    /// Check the checksums of this file instead of converting
+    verify: Option<String>,
+    /// VxD module name, the DDB is `NAME_DDB`
+    vxd: Option<String>,
+    vxd_device_id: Option<u16>,
+    vxd_ddk_version: Option<u16>,
+    /// DLL module name
+    dll: Option<String>,
+    exports: Vec<def::Export>,
+    imports: Vec<def::Import>,
+    layout: Option<String>,
+    gc_sections: bool,
+    /// GC roots besides the entry point and exports
+    keep: Vec<String>,
+}
 
-    if args.len() < 2 { eprintln!("Not enough args"); }
+/// Reads a file given on the command line, naming it if that fails.
+fn read_file(path: &str) -> error::Result<Vec<u8>> {
+    fs::read(path).map_err(|source| Error::Read { path: String::from(path), source })
+}
+
+/// Same as `read_file`, for text files.
+fn read_text(path: &str) -> error::Result<String> {
+    fs::read_to_string(path).map_err(|source| Error::Read { path: String::from(path), source })
+}
 
-    let verbose = args[1] == "-v";
+/// Reads and merges the inputs, then converts them.
+fn run(paths: Vec<String>, options: &Options) -> error::Result<()> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        let data = read_file(&path)?;
+        inputs.push((path, data));
+    }
+    let (data, origins) = merge::merge_objects(&inputs, options.verbose)?;
+    convert(&data, &origins, options)
+}
 
-    if verbose && args.len() < 3 { eprintln!("Not enough args"); }
+/// Parses a decimal or 0x-prefixed hex number.
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
 
-    let path = if verbose { &args[2] } else { &args[1] };
-    let data = fs::read(path)?;
-    convert(&data, verbose)?;
+fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let mut options = Options {
+        verbose: false,
+        export_symbols: false,
+        watcom_debug: false,
+        keep_debug: false,
+        debug_elf: None,
+        debug_bases: Vec::new(),
+        stub: None,
+        stub_message: None,
+        bind: None,
+        extender: &extender::PROFILES[0],
+        lx: false,
+        exepack: false,
+        checksums: false,
+        verify: None,
+        vxd: None,
+        vxd_device_id: None,
+        vxd_ddk_version: None,
+        dll: None,
+        exports: Vec::new(),
+        imports: Vec::new(),
+        layout: None,
+        gc_sections: false,
+        keep: Vec::new(),
+    };
+    let mut extender_given = false;
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-v" => options.verbose = true,
+            "--export-symbols" => options.export_symbols = true,
+            "--watcom-debug" => options.watcom_debug = true,
+            "--keep-debug" => options.keep_debug = true,
+            "--debug-elf" => match args.next() {
+                Some(out) => options.debug_elf = Some(out),
+                None => { eprintln!("--debug-elf needs an output file"); std::process::exit(1) }
+            },
+            "--debug-base" => {
+                let base = args.next().and_then(|value| {
+                    let (obj, addr) = value.split_once('=')?;
+                    Some((obj.parse().ok()?, parse_number(addr)?))
+                });
+                match base {
+                    Some(base) => options.debug_bases.push(base),
+                    None => { eprintln!("--debug-base needs OBJECT=ADDRESS, e.g. 2=0x400000"); std::process::exit(1) }
+                }
+            },
+            "--stub" => match args.next() {
+                Some(stub) => options.stub = Some(stub),
+                None => { eprintln!("--stub needs an MZ executable"); std::process::exit(1) }
+            },
+            "--stub-message" => match args.next() {
+                Some(message) => options.stub_message = Some(message),
+                None => { eprintln!("--stub-message needs a message"); std::process::exit(1) }
+            },
+            "--bind" => match args.next() {
+                Some(extender) => options.bind = Some(extender),
+                None => { eprintln!("--bind needs an extender stub, e.g. stub32a.exe"); std::process::exit(1) }
+            },
+            "--extender" => match args.next().as_deref().and_then(extender::find) {
+                Some(profile) => { options.extender = profile; extender_given = true },
+                None => {
+                    let names: Vec<&str> = extender::PROFILES.iter().map(|profile| profile.name).collect();
+                    eprintln!("--extender needs one of: {}", names.join(", "));
+                    std::process::exit(1)
+                }
+            },
+            "--lx" => options.lx = true,
+            "--exepack" => options.exepack = true,
+            "--checksums" => options.checksums = true,
+            "--verify" => match args.next() {
+                Some(path) => options.verify = Some(path),
+                None => { eprintln!("--verify needs an LE or LX file"); std::process::exit(1) }
+            },
+            "--vxd" => match args.next() {
+                Some(name) if !name.is_empty() && name.len() <= 8 && name.is_ascii() => options.vxd = Some(name),
+                _ => { eprintln!("--vxd needs a device name of up to 8 characters"); std::process::exit(1) }
+            },
+            "--vxd-id" => match args.next().as_deref().and_then(parse_number).and_then(|id| u16::try_from(id).ok()) {
+                Some(id) => options.vxd_device_id = Some(id),
+                None => { eprintln!("--vxd-id needs a 16-bit device ID"); std::process::exit(1) }
+            },
+            "--vxd-ddk" => match args.next().as_deref().and_then(parse_number).and_then(|ver| u16::try_from(ver).ok()) {
+                Some(ver) => options.vxd_ddk_version = Some(ver),
+                None => { eprintln!("--vxd-ddk needs a version, e.g. 0x30A for 3.10"); std::process::exit(1) }
+            },
+            "--dll" => match args.next() {
+                Some(name) if !name.is_empty() && name.len() <= 0xFF => options.dll = Some(name),
+                _ => { eprintln!("--dll needs a module name"); std::process::exit(1) }
+            },
+            "--export" => match args.next().map(|spec| def::parse_export(&spec)) {
+                Some(Ok(export)) => options.exports.push(export),
+                Some(Err(err)) => { eprintln!("--export: {}", err); std::process::exit(1) }
+                None => { eprintln!("--export needs NAME[=SYMBOL][@ORDINAL]"); std::process::exit(1) }
+            },
+            "--exports" => match args.next() {
+                Some(def_path) => match read_text(&def_path).map(|text| def::parse_def(&text)) {
+                    Ok(Ok(defs)) => options.exports.extend(defs.exports),
+                    Ok(Err(err)) => { eprintln!("{}: {}", def_path, err); std::process::exit(1) }
+                    Err(err) => { eprintln!("{}", err); std::process::exit(1) }
+                },
+                None => { eprintln!("--exports needs a .def file"); std::process::exit(1) }
+            },
+            "--import" => match args.next().map(|spec| def::parse_import(&spec)) {
+                Some(Ok(import)) => options.imports.push(import),
+                Some(Err(err)) => { eprintln!("--import: {}", err); std::process::exit(1) }
+                None => { eprintln!("--import needs [SYMBOL=]MODULE.NAME or SYMBOL=MODULE.ORDINAL"); std::process::exit(1) }
+            },
+            "--imports" => match args.next() {
+                Some(def_path) => match read_text(&def_path).map(|text| def::parse_def(&text)) {
+                    Ok(Ok(defs)) => options.imports.extend(defs.imports),
+                    Ok(Err(err)) => { eprintln!("{}: {}", def_path, err); std::process::exit(1) }
+                    Err(err) => { eprintln!("{}", err); std::process::exit(1) }
+                },
+                None => { eprintln!("--imports needs a .def file"); std::process::exit(1) }
+            },
+            "--layout" => match args.next() {
+                Some(layout_path) => options.layout = Some(layout_path),
+                None => { eprintln!("--layout needs a layout file"); std::process::exit(1) }
+            },
+            "--gc-sections" => options.gc_sections = true,
+            "--keep" => match args.next() {
+                Some(symbol) => options.keep.push(symbol),
+                None => { eprintln!("--keep needs a symbol"); std::process::exit(1) }
+            },
+            _ => paths.push(arg),
+        }
+    }
+
+    if options.watcom_debug && options.keep_debug {
+        eprintln!("--watcom-debug and --keep-debug both need the LE debug info fields, pick one");
+        std::process::exit(1)
+    }
+    if options.bind.is_some() && (options.stub.is_some() || options.stub_message.is_some()) {
+        eprintln!("--bind replaces the stub, it can't be combined with --stub or --stub-message");
+        std::process::exit(1)
+    }
+    if options.stub.is_some() && options.stub_message.is_some() {
+        eprintln!("--stub-message only applies to the built-in stub, not --stub");
+        std::process::exit(1)
+    }
+    if options.vxd.is_none() && (options.vxd_device_id.is_some() || options.vxd_ddk_version.is_some()) {
+        eprintln!("--vxd-id and --vxd-ddk need --vxd NAME");
+        std::process::exit(1)
+    }
+    if options.vxd.is_some() {
+        if extender_given || options.bind.is_some() || options.export_symbols {
+            eprintln!("--vxd is loaded by Windows, it can't be combined with --extender, --bind or --export-symbols");
+            std::process::exit(1)
+        }
+        options.extender = &extender::VXD;
+    }
+    if options.dll.is_none() && !options.exports.is_empty() {
+        eprintln!("--export and --exports need --dll NAME");
+        std::process::exit(1)
+    }
+    if options.dll.is_some() && (options.vxd.is_some() || options.export_symbols) {
+        eprintln!("--dll has its own exports, it can't be combined with --vxd or --export-symbols");
+        std::process::exit(1)
+    }
+    if options.exepack && !options.lx {
+        eprintln!("--exepack needs --lx, LE has no compressed pages");
+        std::process::exit(1)
+    }
+    if options.lx && options.vxd.is_some() {
+        eprintln!("--lx doesn't apply to --vxd, Windows only loads LE VxDs");
+        std::process::exit(1)
+    }
+    if !options.keep.is_empty() && !options.gc_sections {
+        eprintln!("--keep only means something with --gc-sections");
+        std::process::exit(1)
+    }
+    if let Some(path) = &options.verify {
+        match read_file(path).and_then(|file| checksum::verify(&file)) {
+            Ok(checks) => {
+                if checks.is_empty() { println!("{}: no checksums", path); }
+                for check in checks.iter().filter(|check| options.verbose || !check.ok()) {
+                    println!("{}: {} checksum 0x{:08x}, contents give 0x{:08x}", path, check.what, check.stored, check.computed);
+                }
+                let bad = checks.iter().filter(|check| !check.ok()).count();
+                if bad > 0 {
+                    eprintln!("{}: {} of {} checksums don't match", path, bad, checks.len());
+                    std::process::exit(1)
+                }
+                if !checks.is_empty() { println!("{}: {} checksums OK", path, checks.len()); }
+            }
+            Err(err) => { eprintln!("{}", err); std::process::exit(1) }
+        }
+        return Ok(())
+    }
+    if paths.is_empty() {
+        eprintln!("Not enough args");
+        std::process::exit(1)
+    }
+    if let Err(err) = run(paths, &options) {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    }
 
     Ok(())
 }
@@ -0,0 +1,372 @@
+//! Combining several input objects into one, before conversion.
+//!
+//! Loaded sections are carried over one for one so the layout can still pick
+//! them individually. `.debug_*` sections are concatenated by name, which is
+//! how DWARF expects to be linked. Globals are resolved by name, locals stay
+//! with their file. Even a single input goes through here, so what the rest
+//! of the converter sees never has COMMON symbols or groups.
+//!
+//! A strong definition overrides weak ones. COMMON symbols nothing defines
+//! are allocated in a `COMMON` bss section, as big and as aligned as the
+//! biggest and most aligned of them.
+//!
+//! Of each COMDAT group only the first copy is kept. Sections of later copies
+//! are dropped and anything referring to them is pointed at the kept copy's
+//! section of the same name, which holds the same code or data.
+
+use std::collections::HashMap;
+
+use crate::error::{self, Error};
+
+use object::{ComdatKind, Object, ObjectComdat, ObjectSection, ObjectSymbol, RelocationTarget, SectionIndex, SymbolIndex, SymbolKind, SymbolScope};
+use object::write::{SectionId, SymbolId, SymbolSection};
+
+type InputSection = (usize, SectionIndex);
+
+/// Where an input section went: output section and offset in it. `None` for
+/// dropped group copies with no counterpart in the kept copy.
+type SectionMap = HashMap<InputSection, Option<(SectionId, u64)>>;
+
+fn is_loaded(section: &object::Section) -> bool {
+    !matches!(section.kind(), object::SectionKind::Metadata | object::SectionKind::Linker | object::SectionKind::Note
+        | object::SectionKind::Other | object::SectionKind::OtherString | object::SectionKind::Debug | object::SectionKind::Unknown)
+}
+
+fn is_debug(section: &object::Section) -> bool {
+    section.name().is_ok_and(|name| name.starts_with(".debug_"))
+}
+
+/// Finds the group copies to drop, mapped to the kept copy's sections.
+fn discarded_sections(files: &[object::File], verbose: bool) -> HashMap<InputSection, Option<InputSection>> {
+    let mut kept: HashMap<String, (usize, Vec<SectionIndex>)> = HashMap::new();
+    let mut discarded = HashMap::new();
+    for (file_idx, file) in files.iter().enumerate() {
+        for comdat in file.comdats() {
+            if comdat.kind() != ComdatKind::Any { continue }
+            let signature = String::from(comdat.name().unwrap_or(""));
+            match kept.get(&signature) {
+                Some((kept_file, kept_sections)) => {
+                    if verbose { println!("discarding duplicate group {} in input {}", signature, file_idx + 1); }
+                    for index in comdat.sections() {
+                        let name = file.section_by_index(index).ok().and_then(|section| section.name().ok().map(String::from));
+                        let counterpart = kept_sections.iter().copied().find(|kept_index| {
+                            files[*kept_file].section_by_index(*kept_index).is_ok_and(|section| section.name().ok() == name.as_deref())
+                        });
+                        discarded.insert((file_idx, index), counterpart.map(|kept_index| (*kept_file, kept_index)));
+                    }
+                }
+                None => { kept.insert(signature, (file_idx, comdat.sections().collect())); }
+            }
+        }
+    }
+    discarded
+}
+
+/// Assembler labels are NOTYPE, they're code or data by where they are.
+fn symbol_kind(file: &object::File, symbol: &object::Symbol) -> SymbolKind {
+    let code = symbol.section_index()
+        .and_then(|index| file.section_by_index(index).ok())
+        .is_some_and(|section| section.kind() == object::SectionKind::Text);
+    match symbol.kind() {
+        SymbolKind::Unknown | SymbolKind::Label if code => SymbolKind::Text,
+        SymbolKind::Unknown | SymbolKind::Label => SymbolKind::Data,
+        kind => kind
+    }
+}
+
+/// Offset of a symbol in its section. Sections of ELF objects all start at
+/// 0, but not those of other formats.
+fn section_offset(file: &object::File, symbol: &object::Symbol) -> u64 {
+    let section_address = symbol.section_index()
+        .and_then(|index| file.section_by_index(index).ok())
+        .map_or(0, |section| section.address());
+    symbol.address() - section_address
+}
+
+fn read_addend(data: &[u8], offset: usize, size: u8) -> Option<i64> {
+    Some(match size {
+        8 => *data.get(offset)? as i8 as i64,
+        16 => i16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?) as i64,
+        32 => i32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as i64,
+        _ => return None
+    })
+}
+
+fn write_addend(data: &mut [u8], offset: usize, size: u8, value: i64) {
+    match size {
+        8 => data[offset] = value as u8,
+        16 => data[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes()),
+        _ => data[offset..offset + 4].copy_from_slice(&(value as u32).to_le_bytes()),
+    }
+}
+
+/// Which input each section of the merged object came from, for errors.
+pub type Origins = HashMap<SectionIndex, String>;
+
+fn parse_error(path: &str) -> impl Fn(object::Error) -> Error + '_ {
+    move |source| Error::Parse { path: String::from(path), source }
+}
+
+/// Merges relocatable i386 objects into one relocatable object.
+pub fn merge_objects(inputs: &[(String, Vec<u8>)], verbose: bool) -> error::Result<(Vec<u8>, Origins)> {
+    let mut files = Vec::new();
+    for (path, data) in inputs {
+        files.push(object::File::parse(&**data).map_err(parse_error(path))?);
+    }
+    let discarded = discarded_sections(&files, verbose);
+
+    let mut new_elf = object::write::Object::new(object::BinaryFormat::Elf, object::Architecture::I386, object::Endianness::Little);
+    let mut sections: SectionMap = HashMap::new();
+    let mut debug_sections: HashMap<String, SectionId> = HashMap::new();
+    let mut section_origins: Vec<&str> = Vec::new();
+    for (file_idx, file) in files.iter().enumerate() {
+        let path = &inputs[file_idx].0;
+        for section in file.sections() {
+            if discarded.contains_key(&(file_idx, section.index())) { continue }
+            let name = section.name().map_err(parse_error(path))?;
+            let debug = is_debug(&section);
+            if !debug && !is_loaded(&section) { continue }
+            let data = section.uncompressed_data().map_err(parse_error(path))?;
+            let id = match debug_sections.get(name) {
+                Some(id) if debug => *id,
+                _ => {
+                    let id = new_elf.add_section(Vec::new(), Vec::from(name), section.kind());
+                    if let object::SectionFlags::Elf { sh_flags } = section.flags() {
+                        // The groups are resolved here, they don't go in the output
+                        new_elf.section_mut(id).flags = object::SectionFlags::Elf { sh_flags: sh_flags & !(object::elf::SHF_GROUP as u64) };
+                    }
+                    if debug { debug_sections.insert(String::from(name), id); }
+                    section_origins.push(path);
+                    id
+                }
+            };
+            let offset = if section.kind() == object::SectionKind::UninitializedData {
+                new_elf.append_section_bss(id, section.size(), section.align().max(1))
+            } else {
+                new_elf.append_section_data(id, &data, section.align().max(1))
+            };
+            sections.insert((file_idx, section.index()), Some((id, offset)));
+        }
+    }
+    for (dropped, counterpart) in discarded.iter() {
+        let target = counterpart.and_then(|kept| sections.get(&kept).copied().flatten());
+        sections.insert(*dropped, target);
+    }
+
+    // A strong definition of a global beats weak ones, otherwise the first
+    // definition wins. Two strong ones are an error.
+    let mut globals: HashMap<String, (usize, SymbolIndex)> = HashMap::new();
+    // COMMON symbols nothing defines, (size, alignment) of the biggest
+    let mut commons: HashMap<String, (u64, u64)> = HashMap::new();
+    for (file_idx, file) in files.iter().enumerate() {
+        let path = &inputs[file_idx].0;
+        for symbol in file.symbols() {
+            if symbol.is_common() {
+                // The value of a COMMON symbol is its alignment
+                let common = commons.entry(String::from(symbol.name().map_err(parse_error(path))?)).or_insert((0, 1));
+                *common = (common.0.max(symbol.size()), common.1.max(symbol.address()));
+                continue
+            }
+            if symbol.is_local() || symbol.is_undefined() { continue }
+            let defined = match symbol.section_index() {
+                Some(index) => !discarded.contains_key(&(file_idx, index)),
+                None => true
+            };
+            if !defined { continue }
+            let name = String::from(symbol.name().map_err(parse_error(path))?);
+            match globals.get(&name) {
+                Some((other_file, other_idx)) => {
+                    let other = files[*other_file].symbol_by_index(*other_idx).map_err(parse_error(&inputs[*other_file].0))?;
+                    if !symbol.is_weak() && !other.is_weak() {
+                        return Err(Error::DuplicateSymbol { name, first: inputs[*other_file].0.clone(), second: path.clone() });
+                    }
+                    if other.is_weak() && !symbol.is_weak() {
+                        if verbose { println!("{} in {} overrides the weak one in {}", name, inputs[file_idx].0, inputs[*other_file].0); }
+                        globals.insert(name, (file_idx, symbol.index()));
+                    }
+                }
+                None => { globals.insert(name, (file_idx, symbol.index())); }
+            }
+        }
+    }
+
+    let mut new_globals: HashMap<String, SymbolId> = HashMap::new();
+    let mut new_locals: HashMap<(usize, SymbolIndex), SymbolId> = HashMap::new();
+    let mut common_sec = None;
+    for (file_idx, file) in files.iter().enumerate() {
+        let path = &inputs[file_idx].0;
+        for symbol in file.symbols() {
+            if matches!(symbol.kind(), SymbolKind::Section | SymbolKind::File | SymbolKind::Null) { continue }
+            let name = symbol.name().map_err(parse_error(path))?;
+            if !symbol.is_local() {
+                if let Some(&(def_file, def_idx)) = globals.get(name) {
+                    if def_file != file_idx || new_globals.contains_key(name) { continue }
+                    let def = file.symbol_by_index(def_idx).map_err(parse_error(path))?;
+                    let section = match def.section_index() {
+                        Some(index) => sections.get(&(file_idx, index)).copied().flatten()
+                            .map(|(id, offset)| (SymbolSection::Section(id), offset + section_offset(file, &def))),
+                        None => Some((SymbolSection::Absolute, def.address()))
+                    };
+                    let (section, value) = match section {
+                        Some(section) => section,
+                        None => continue
+                    };
+                    let new_symbol = new_elf.add_symbol(object::write::Symbol {
+                        name: Vec::from(name),
+                        value,
+                        size: def.size(),
+                        kind: symbol_kind(file, &def),
+                        // STB_GNU_UNIQUE reads back as an unknown scope
+                        scope: match def.scope() {
+                            SymbolScope::Unknown => SymbolScope::Dynamic,
+                            scope => scope
+                        },
+                        weak: def.is_weak(),
+                        section,
+                        flags: object::SymbolFlags::None,
+                    });
+                    new_globals.insert(String::from(name), new_symbol);
+                    continue
+                }
+                if new_globals.contains_key(name) {
+                    // Undefined is only weak if every reference is
+                    if !symbol.is_weak() {
+                        new_elf.symbol_mut(new_globals[name]).weak = false;
+                    }
+                    continue
+                }
+                // Commons nothing defined go in bss, like a definition would
+                let new_symbol = match commons.get(name) {
+                    Some(&(size, align)) => {
+                        let common_sec = *common_sec.get_or_insert_with(|| {
+                            new_elf.add_section(Vec::new(), Vec::from("COMMON"), object::SectionKind::UninitializedData)
+                        });
+                        let offset = new_elf.append_section_bss(common_sec, size, align.max(1));
+                        if verbose { println!("allocating common {}, {} bytes at COMMON+0x{:x}", name, size, offset); }
+                        new_elf.add_symbol(object::write::Symbol {
+                            name: Vec::from(name),
+                            value: offset,
+                            size,
+                            kind: SymbolKind::Data,
+                            scope: SymbolScope::Dynamic,
+                            weak: false,
+                            section: SymbolSection::Section(common_sec),
+                            flags: object::SymbolFlags::None,
+                        })
+                    }
+                    None => new_elf.add_symbol(object::write::Symbol {
+                        name: Vec::from(name),
+                        value: 0,
+                        size: 0,
+                        kind: symbol.kind(),
+                        scope: symbol.scope(),
+                        weak: symbol.is_weak(),
+                        section: SymbolSection::Undefined,
+                        flags: object::SymbolFlags::None,
+                    })
+                };
+                new_globals.insert(String::from(name), new_symbol);
+                continue
+            }
+            // Locals of dropped sections are reached through the kept section
+            let section = match symbol.section_index() {
+                Some(index) if discarded.contains_key(&(file_idx, index)) => continue,
+                Some(index) => match sections.get(&(file_idx, index)).copied().flatten() {
+                    Some((id, offset)) => (SymbolSection::Section(id), offset + section_offset(file, &symbol)),
+                    None => continue
+                },
+                None if symbol.is_undefined() => continue,
+                None => (SymbolSection::Absolute, symbol.address())
+            };
+            let new_symbol = new_elf.add_symbol(object::write::Symbol {
+                name: Vec::from(name),
+                value: section.1,
+                size: symbol.size(),
+                kind: symbol_kind(file, &symbol),
+                scope: symbol.scope(),
+                weak: false,
+                section: section.0,
+                flags: object::SymbolFlags::None,
+            });
+            new_locals.insert((file_idx, symbol.index()), new_symbol);
+        }
+    }
+
+    for (file_idx, file) in files.iter().enumerate() {
+        let path = &inputs[file_idx].0;
+        for section in file.sections() {
+            let (new_sec, base) = match sections.get(&(file_idx, section.index())) {
+                Some(Some(placed)) if !discarded.contains_key(&(file_idx, section.index())) => *placed,
+                _ => continue
+            };
+            for (src, reloc) in section.relocations() {
+                // (symbol, amount to add to the addend)
+                let target = match reloc.target() {
+                    RelocationTarget::Symbol(sym_idx) => {
+                        let symbol = file.symbol_by_index(sym_idx).map_err(parse_error(path))?;
+                        if let Some(new) = new_locals.get(&(file_idx, sym_idx)) {
+                            Some((*new, 0))
+                        } else if !symbol.is_local() {
+                            new_globals.get(symbol.name().map_err(parse_error(path))?).map(|new| (*new, 0))
+                        } else {
+                            // Section symbols, and locals in dropped group copies
+                            let address = section_offset(file, &symbol);
+                            symbol.section_index()
+                                .and_then(|index| sections.get(&(file_idx, index)).copied().flatten())
+                                .map(|(id, offset)| (new_elf.section_symbol(id), (offset + address) as i64))
+                        }
+                    }
+                    // The addend is from the start of the input, not of the section
+                    RelocationTarget::Section(index) => {
+                        let address = file.section_by_index(index).map_err(parse_error(path))?.address();
+                        sections.get(&(file_idx, index)).copied().flatten()
+                            .map(|(id, offset)| (new_elf.section_symbol(id), offset as i64 - address as i64))
+                    }
+                    // No symbol at all, the value is all in the addend
+                    _ => continue
+                };
+                let (new_symbol, delta) = match target {
+                    Some(target) => target,
+                    None => {
+                        eprintln!("Warning: {}: relocation at 0x{:x} in {} refers to a dropped section", path, src, section.name().unwrap_or(""));
+                        continue
+                    }
+                };
+                let offset = base + src;
+                let addend = if reloc.has_implicit_addend() {
+                    let data = new_elf.section_mut(new_sec).data_mut();
+                    let value = read_addend(data, offset as usize, reloc.size()).ok_or_else(|| {
+                        let (file, section, offset) = (path.clone(), String::from(section.name().unwrap_or("")), src);
+                        match reloc.size() {
+                            8 | 16 | 32 => Error::OffsetOverflow { file, section, offset },
+                            size => Error::UnsupportedRelocation { file, section, offset, kind: format!("{:?} of {} bits", reloc.kind(), size) }
+                        }
+                    })? + delta;
+                    write_addend(data, offset as usize, reloc.size(), value);
+                    if reloc.size() == 32 { value } else { 0 }
+                } else {
+                    reloc.addend() + delta
+                };
+                new_elf.add_relocation(new_sec, object::write::Relocation {
+                    offset,
+                    size: reloc.size(),
+                    kind: reloc.kind(),
+                    encoding: reloc.encoding(),
+                    symbol: new_symbol,
+                    addend,
+                })?;
+            }
+        }
+    }
+    let merged = new_elf.write()?;
+
+    // The output has the sections in the order they were added, with the
+    // relocation and symbol sections in between
+    let mut origins = Origins::new();
+    let merged_file = object::File::parse(&*merged)?;
+    let merged_sections = merged_file.sections().filter(|section| section.kind() != object::SectionKind::Metadata);
+    for (section, path) in merged_sections.zip(section_origins) {
+        origins.insert(section.index(), String::from(path));
+    }
+    Ok((merged, origins))
+}
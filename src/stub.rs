@@ -0,0 +1,105 @@
+//! The MZ program in front of the LE header.
+//!
+//! Plain DOS runs this when the executable is started without an extender.
+//! The LE header follows it, found through `e_lfanew` at 0x3C.
+
+pub const DEFAULT_MESSAGE: &str = "This program cannot be run in DOS mode. (idiot)";
+
+const MZ_HEADER_LEN: usize = 0x40;
+
+/// Offset of `e_lfanew`, the new-style header pointer
+const E_LFANEW: usize = 0x3C;
+
+fn write_u16(stub: &mut [u8], offset: usize, value: u16) {
+    stub[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u16(stub: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([stub[offset], stub[offset + 1]])
+}
+
+/// Builds the default stub: an MZ header and a few bytes of 16-bit code
+/// that print `message` and exit with errorlevel 1.
+pub fn build_stub(message: &str) -> Result<Vec<u8>, String> {
+    if message.contains('$') {
+        return Err(String::from("stub message can't contain '$', DOS uses it to end the string"));
+    }
+    if !message.is_ascii() {
+        return Err(String::from("stub message must be ASCII"));
+    }
+    let mut stub = vec![0u8; MZ_HEADER_LEN];
+    stub[0x00..0x02].copy_from_slice(b"MZ");
+    // Paragraphs in header
+    write_u16(&mut stub, 0x08, (MZ_HEADER_LEN / 16) as u16);
+    // Paragraphs of additional memory, min and max
+    write_u16(&mut stub, 0x0A, 0x0000);
+    write_u16(&mut stub, 0x0C, 0xFFFF);
+    // SS:SP
+    write_u16(&mut stub, 0x0E, 0x0000);
+    write_u16(&mut stub, 0x10, 0x0500);
+    // CS:IP
+    write_u16(&mut stub, 0x14, 0x0000);
+    write_u16(&mut stub, 0x16, 0x0000);
+    // Offset of first relocation, 0x40 for new-style executables
+    write_u16(&mut stub, 0x18, MZ_HEADER_LEN as u16);
+
+    // push cs; pop ds; mov dx, message; mov ah, 9; int 21h; mov ax, 4C01h; int 21h
+    let code_len = 14u16;
+    stub.extend_from_slice(&[0x0E, 0x1F, 0xBA]);
+    stub.extend_from_slice(&code_len.to_le_bytes());
+    stub.extend_from_slice(&[0xB4, 0x09, 0xCD, 0x21, 0xB8, 0x01, 0x4C, 0xCD, 0x21]);
+    stub.extend_from_slice(message.as_bytes());
+    stub.extend_from_slice(b"\r\n$");
+
+    // Bytes in last block, blocks in file
+    let image_len = stub.len();
+    write_u16(&mut stub, 0x02, (image_len % 512) as u16);
+    write_u16(&mut stub, 0x04, image_len.div_ceil(512) as u16);
+    Ok(stub)
+}
+
+/// Checks a user supplied MZ program can be used as the stub.
+pub fn load_stub(stub: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut stub = stub;
+    if stub.len() < MZ_HEADER_LEN || &stub[0x00..0x02] != b"MZ" {
+        return Err(String::from("stub is not an MZ executable"));
+    }
+    if (read_u16(&stub, 0x08) as usize) * 16 < MZ_HEADER_LEN {
+        return Err(String::from("stub's MZ header is too small to hold e_lfanew at 0x3C"));
+    }
+    // Loaders only trust e_lfanew if the relocations start past it
+    if (read_u16(&stub, 0x18) as usize) < MZ_HEADER_LEN {
+        if read_u16(&stub, 0x06) != 0 {
+            return Err(String::from("stub's relocation table overlaps e_lfanew at 0x3C"));
+        }
+        write_u16(&mut stub, 0x18, MZ_HEADER_LEN as u16);
+    }
+    Ok(stub)
+}
+
+/// Takes an extender (stub32a.exe, pmodew.exe, ...) to bind in front of the
+/// LE image. Only its MZ load image is kept, since extenders look for the LE
+/// header where that ends as well as through `e_lfanew`.
+pub fn load_extender(stub: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut stub = load_stub(stub)?;
+    let blocks = read_u16(&stub, 0x04) as usize;
+    let last_block_bytes = read_u16(&stub, 0x02) as usize;
+    let image_len = match last_block_bytes {
+        0 => blocks * 512,
+        _ => blocks.saturating_sub(1) * 512 + last_block_bytes
+    };
+    if image_len < MZ_HEADER_LEN || image_len > stub.len() {
+        return Err(format!("extender's MZ header says the image is {} bytes, the file has {}", image_len, stub.len()));
+    }
+    stub.truncate(image_len);
+    Ok(stub)
+}
+
+/// Pads the stub to a multiple of `align` for the LE header and points
+/// `e_lfanew` at it. Returns the offset of the LE header.
+pub fn place_le_header(stub: &mut Vec<u8>, align: usize) -> u64 {
+    let le_header_offset = stub.len().next_multiple_of(align);
+    stub.resize(le_header_offset, 0);
+    stub[E_LFANEW..E_LFANEW + 4].copy_from_slice(&(le_header_offset as u32).to_le_bytes());
+    le_header_offset as u64
+}
@@ -0,0 +1,171 @@
+//! Watcom debug information (version 3), as read by WD.
+//!
+//! The info sits at the very end of the executable and the debugger finds it
+//! from the master header, which is the last thing in the file:
+//!
+//!   language table    "C\0"
+//!   segment table     u16 LE object number per segment
+//!   section header    offsets below are from the start of this header
+//!     line blocks, then a link table per module
+//!     module info
+//!     global info
+//!     address info
+//!   master header
+//!
+//! Only line numbers, globals and address ranges are written; locals and
+//! types are left empty.
+
+use crate::debug::{DebugModule, DebugSymbol};
+
+const VALID_SIGNATURE: u16 = 0x8386;
+const EXE_MAJOR_VERSION: u8 = 3;
+const EXE_MINOR_VERSION: u8 = 0;
+const OBJ_MAJOR_VERSION: u8 = 1;
+const OBJ_MINOR_VERSION: u8 = 1;
+
+const GBL_KIND_STATIC: u8 = 0x01;
+const GBL_KIND_CODE: u8 = 0x02;
+const GBL_KIND_DATA: u8 = 0x04;
+
+/// Line entries per demand-loaded block
+const LINES_PER_BLOCK: usize = 0x1000;
+
+fn push_name(buf: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..name.len().min(0xFF)];
+    buf.push(name.len() as u8);
+    buf.extend_from_slice(name);
+}
+
+fn push_addr48(buf: &mut Vec<u8>, offset: u32, segment: u16) {
+    buf.extend_from_slice(&offset.to_le_bytes());
+    buf.extend_from_slice(&segment.to_le_bytes());
+}
+
+/// Builds the complete debug info blob. `object_sizes` holds the size of each
+/// LE object in object number order; the modules' lines are in
+/// `code_object`.
+pub fn build_debug_info(modules: &[DebugModule], globals: &[DebugSymbol], object_sizes: &[u32], code_object: u16) -> Vec<u8> {
+    let placeholder = [DebugModule { name: String::from("ELFLE"), lines: Vec::new() }];
+    let modules = if modules.is_empty() { &placeholder[..] } else { modules };
+
+    let lang_table = b"C\0";
+    let mut seg_table = Vec::new();
+    for obj in 1..=object_sizes.len() as u16 {
+        seg_table.extend_from_slice(&obj.to_le_bytes());
+    }
+
+    // Section header is filled in last, everything is relative to its start
+    let section_header_len = 4 * 4 + 2;
+    let mut section = vec![0u8; section_header_len];
+
+    // Line blocks and their link tables: (link table offset, block count)
+    let mut line_links = Vec::new();
+    for module in modules {
+        let mut links = Vec::new();
+        for block in module.lines.chunks(LINES_PER_BLOCK) {
+            links.push(section.len() as u32);
+            section.extend_from_slice(&(code_object as u32).to_le_bytes());
+            section.extend_from_slice(&(block.len() as u16).to_le_bytes());
+            for (offset, line) in block {
+                section.extend_from_slice(&((*line).min(0xFFFF) as u16).to_le_bytes());
+                section.extend_from_slice(&offset.to_le_bytes());
+            }
+        }
+        if links.is_empty() {
+            line_links.push((0, 0));
+            continue
+        }
+        // Link table ends with the end of the last block
+        links.push(section.len() as u32);
+        line_links.push((section.len() as u32, links.len() as u16 - 1));
+        for link in links {
+            section.extend_from_slice(&link.to_le_bytes());
+        }
+    }
+
+    // Module info
+    let mod_offset = section.len() as u32;
+    for (module, (lines_off, lines_count)) in modules.iter().zip(line_links) {
+        // Language, as an offset into the language table
+        section.extend_from_slice(&0u16.to_le_bytes());
+        // Demand info for locals, types, lines
+        section.extend_from_slice(&[0u8; 6]);
+        section.extend_from_slice(&[0u8; 6]);
+        section.extend_from_slice(&lines_off.to_le_bytes());
+        section.extend_from_slice(&lines_count.to_le_bytes());
+        push_name(&mut section, &module.name);
+    }
+
+    // Code ranges: each module owns from its first line to the next module's
+    let mut code_ranges: Vec<(u32, u16)> = modules.iter().enumerate()
+        .filter_map(|(idx, module)| module.lines.first().map(|(offset, _)| (*offset, idx as u16)))
+        .collect();
+    code_ranges.sort();
+    if code_ranges.is_empty() {
+        code_ranges.push((0, 0));
+    }
+    code_ranges[0].0 = 0;
+    let module_at = |offset: u32| -> u16 {
+        match code_ranges.iter().rev().find(|(start, _)| *start <= offset) {
+            Some((_, module)) => *module,
+            None => 0
+        }
+    };
+
+    // Global info
+    let gbl_offset = section.len() as u32;
+    for global in globals {
+        let offset = global.offset as u32;
+        push_addr48(&mut section, offset, global.object as u16);
+        let in_code = global.code && global.object as u16 == code_object;
+        section.extend_from_slice(&(if in_code { module_at(offset) } else { 0 }).to_le_bytes());
+        let mut kind = if global.code { GBL_KIND_CODE } else { GBL_KIND_DATA };
+        if !global.global { kind |= GBL_KIND_STATIC; }
+        section.push(kind);
+        push_name(&mut section, &global.name);
+    }
+
+    // Address info, one segment block per LE object
+    let addr_offset = section.len() as u32;
+    for (idx, size) in object_sizes.iter().enumerate() {
+        let object = idx as u16 + 1;
+        push_addr48(&mut section, 0, object);
+        if object == code_object {
+            section.extend_from_slice(&(code_ranges.len() as u16).to_le_bytes());
+            for (range_idx, (start, module)) in code_ranges.iter().enumerate() {
+                let end = match code_ranges.get(range_idx + 1) {
+                    Some((next, _)) => *next,
+                    None => (*size).max(*start)
+                };
+                section.extend_from_slice(&(end - start).to_le_bytes());
+                section.extend_from_slice(&module.to_le_bytes());
+            }
+        } else {
+            section.extend_from_slice(&1u16.to_le_bytes());
+            section.extend_from_slice(&size.to_le_bytes());
+            section.extend_from_slice(&0u16.to_le_bytes());
+        }
+    }
+
+    let section_size = section.len() as u32;
+    section[0x00..0x04].copy_from_slice(&mod_offset.to_le_bytes());
+    section[0x04..0x08].copy_from_slice(&gbl_offset.to_le_bytes());
+    section[0x08..0x0C].copy_from_slice(&addr_offset.to_le_bytes());
+    section[0x0C..0x10].copy_from_slice(&section_size.to_le_bytes());
+    // Section id 0, the only overlay section
+    section[0x10..0x12].copy_from_slice(&0u16.to_le_bytes());
+
+    let master_header_len = 2 + 4 + 2 + 2 + 4;
+    let debug_size = lang_table.len() + seg_table.len() + section.len() + master_header_len;
+
+    let mut info = Vec::with_capacity(debug_size);
+    info.extend_from_slice(lang_table);
+    info.extend_from_slice(&seg_table);
+    info.extend_from_slice(&section);
+    info.extend_from_slice(&VALID_SIGNATURE.to_le_bytes());
+    info.extend_from_slice(&[EXE_MAJOR_VERSION, EXE_MINOR_VERSION, OBJ_MAJOR_VERSION, OBJ_MINOR_VERSION]);
+    info.extend_from_slice(&(lang_table.len() as u16).to_le_bytes());
+    info.extend_from_slice(&(seg_table.len() as u16).to_le_bytes());
+    info.extend_from_slice(&(debug_size as u32).to_le_bytes());
+    info
+}
@@ -0,0 +1,818 @@
+//! Runs elf2le on objects built here and looks at what it wrote.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use object::write::{Object, Relocation, SectionId, Symbol, SymbolId, SymbolSection};
+use object::{Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationKind, SectionKind, SymbolFlags, SymbolKind, SymbolScope};
+
+fn new_object() -> Object<'static> {
+    Object::new(BinaryFormat::Elf, Architecture::I386, Endianness::Little)
+}
+
+fn add_section(obj: &mut Object, name: &str, kind: SectionKind, data: &[u8], align: u64) -> SectionId {
+    let section = obj.add_section(Vec::new(), Vec::from(name), kind);
+    obj.append_section_data(section, data, align);
+    section
+}
+
+fn add_symbol(obj: &mut Object, name: &str, section: SectionId, value: u64, kind: SymbolKind) -> SymbolId {
+    obj.add_symbol(Symbol {
+        name: Vec::from(name),
+        value,
+        size: 0,
+        kind,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Section(section),
+        flags: SymbolFlags::None,
+    })
+}
+
+fn add_relocation(obj: &mut Object, section: SectionId, offset: u64, symbol: SymbolId, kind: RelocationKind, size: u8, addend: i64) {
+    obj.add_relocation(section, Relocation { offset, size, kind, encoding: RelocationEncoding::Generic, symbol, addend }).unwrap();
+}
+
+/// What a run of elf2le left behind.
+struct Run {
+    dir: PathBuf,
+    success: bool,
+    stderr: String,
+}
+
+impl Run {
+    fn exe(&self) -> Le {
+        Le(std::fs::read(self.dir.join("a.exe")).unwrap())
+    }
+}
+
+/// Writes `inputs` to a directory of their own and converts them there.
+fn convert(name: &str, inputs: &[Object], args: &[&str]) -> Run {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_elf2le"));
+    command.current_dir(&dir).args(args);
+    for (idx, input) in inputs.iter().enumerate() {
+        let path = dir.join(format!("in{}.o", idx));
+        std::fs::write(&path, input.write().unwrap()).unwrap();
+        command.arg(path);
+    }
+    let output = command.output().unwrap();
+    Run { dir, success: output.status.success(), stderr: String::from_utf8_lossy(&output.stderr).into_owned() }
+}
+
+/// An internal LE fixup: page, source offset in it, source type and target
+/// object and offset.
+#[derive(Debug, PartialEq)]
+struct Fixup {
+    page: u32,
+    source: i16,
+    source_type: u8,
+    target_object: u8,
+    target_offset: u32,
+}
+
+struct Le(Vec<u8>);
+
+impl Le {
+    fn header(&self) -> usize {
+        u32::from_le_bytes(self.0[0x3C..0x40].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.0[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Page map entries of an LE file: page number in the file, flags.
+    fn page_map(&self) -> Vec<(usize, u8)> {
+        let header = self.header();
+        let num_pages = self.u32_at(header + 0x14) as usize;
+        let page_map = header + self.u32_at(header + 0x48) as usize;
+        (0..num_pages)
+            .map(|page| &self.0[page_map + page * 4..page_map + page * 4 + 4])
+            .map(|entry| ((entry[0] as usize) << 16 | (entry[1] as usize) << 8 | entry[2] as usize, entry[3]))
+            .collect()
+    }
+
+    /// What's stored in the file for page `page`, numbered from 1 like the
+    /// fixups' pages. Empty for pages that aren't stored.
+    fn page(&self, page: usize) -> &[u8] {
+        let (number, _) = self.page_map()[page - 1];
+        if number == 0 { return &[] }
+        let start = self.u32_at(self.header() + 0x80) as usize + (number - 1) * 0x1000;
+        &self.0[start..(start + 0x1000).min(self.0.len())]
+    }
+
+    /// Internal fixups, one per source.
+    fn fixups(&self) -> Vec<Fixup> {
+        let header = self.header();
+        let num_pages = self.u32_at(header + 0x14);
+        let page_table = header + self.u32_at(header + 0x68) as usize;
+        let records = header + self.u32_at(header + 0x6C) as usize;
+        let mut fixups = Vec::new();
+        for page in 0..num_pages {
+            let mut pos = records + self.u32_at(page_table + page as usize * 4) as usize;
+            let end = records + self.u32_at(page_table + page as usize * 4 + 4) as usize;
+            while pos < end {
+                let (source_type, flags) = (self.0[pos], self.0[pos + 1]);
+                pos += 2;
+                let mut sources = Vec::new();
+                let count = if source_type & 0x20 != 0 { pos += 1; self.0[pos - 1] as usize } else {
+                    sources.push(i16::from_le_bytes([self.0[pos], self.0[pos + 1]]));
+                    pos += 2;
+                    0
+                };
+                assert_eq!(flags & 0x03, 0, "only internal fixups are looked at");
+                let target_object = self.0[pos];
+                pos += 1;
+                let target_offset = if flags & 0x10 != 0 {
+                    pos += 4;
+                    self.u32_at(pos - 4)
+                } else {
+                    pos += 2;
+                    u16::from_le_bytes([self.0[pos - 2], self.0[pos - 1]]) as u32
+                };
+                for _ in 0..count {
+                    sources.push(i16::from_le_bytes([self.0[pos], self.0[pos + 1]]));
+                    pos += 2;
+                }
+                for source in sources {
+                    fixups.push(Fixup { page: page + 1, source, source_type: source_type & 0x0F, target_object, target_offset });
+                }
+            }
+        }
+        fixups
+    }
+}
+
+#[test]
+fn call_into_the_middle_of_a_section() {
+    let mut obj = new_object();
+    // call .text.b+3; ret
+    let text_a = add_section(&mut obj, ".text.a", SectionKind::Text, &[0xE8, 0, 0, 0, 0, 0xC3], 1);
+    let text_b = add_section(&mut obj, ".text.b", SectionKind::Text, &[0x90, 0x90, 0x90, 0xC3], 1);
+    add_symbol(&mut obj, "start", text_a, 0, SymbolKind::Text);
+    let text_b_symbol = obj.section_symbol(text_b);
+    add_relocation(&mut obj, text_a, 1, text_b_symbol, RelocationKind::Relative, 32, 3 - 4);
+    let run = convert("call_into_the_middle_of_a_section", &[obj], &[]);
+    assert!(run.success, "{}", run.stderr);
+    // .text.b goes right after the 6 bytes of .text.a
+    assert_eq!(run.exe().fixups(), vec![Fixup { page: 1, source: 1, source_type: 0x08, target_object: 1, target_offset: 6 + 3 }]);
+}
+
+#[test]
+fn relocation_against_an_unplaced_section() {
+    let mut obj = new_object();
+    // mov eax, [.data]; ret
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xA1, 0, 0, 0, 0, 0xC3], 1);
+    let data = add_section(&mut obj, ".data", SectionKind::Data, &[1, 2, 3, 4], 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let data_symbol = obj.section_symbol(data);
+    add_relocation(&mut obj, text, 1, data_symbol, RelocationKind::Absolute, 32, 0);
+    // Nothing takes .data
+    let layout = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("code_only.toml");
+    std::fs::write(&layout, "start = { object = \".text\", offset = 0 }\n\n[[object]]\nname = \".text\"\ncode = true\nkinds = [\"text\"]\n").unwrap();
+    let run = convert("relocation_against_an_unplaced_section", &[obj], &["--layout", layout.to_str().unwrap()]);
+    assert!(!run.success);
+    assert!(run.stderr.contains(".text+0x1: relocation refers to .data, which isn't in any LE object"), "{}", run.stderr);
+}
+
+/// `mov eax, [msg]; ret` with `msg` in a `.rodata.str1.1`, as gcc puts it.
+fn string_user() -> Object<'static> {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xA1, 0, 0, 0, 0, 0xC3], 1);
+    let strings = add_section(&mut obj, ".rodata.str1.1", SectionKind::ReadOnlyString, b"hi\0", 1);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let strings_symbol = obj.section_symbol(strings);
+    add_relocation(&mut obj, text, 1, strings_symbol, RelocationKind::Absolute, 32, 0);
+    obj
+}
+
+#[test]
+fn merged_strings_go_with_the_data() {
+    let run = convert("merged_strings_go_with_the_data", &[string_user()], &[]);
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(run.exe().fixups(), vec![Fixup { page: 1, source: 1, source_type: 0x07, target_object: 2, target_offset: 0 }]);
+}
+
+#[test]
+fn merged_strings_are_rodata() {
+    let layout = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("rodata.toml");
+    std::fs::write(&layout, "start = { object = \".text\", offset = 0 }\n\n\
+        [[object]]\nname = \".text\"\ncode = true\nkinds = [\"text\"]\n\n\
+        [[object]]\nname = \".rodata\"\nwritable = false\nkinds = [\"rodata\"]\n").unwrap();
+    let run = convert("merged_strings_are_rodata", &[string_user()], &["--layout", layout.to_str().unwrap()]);
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(run.exe().fixups(), vec![Fixup { page: 1, source: 1, source_type: 0x07, target_object: 2, target_offset: 0 }]);
+}
+
+/// `dw` of `.data`+2 in `.data`, as R_386_16 or R_386_PC16.
+fn word_pointer(kind: RelocationKind) -> Object<'static> {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xC3], 1);
+    // The writer can't put 16-bit addends in the data, so they're there already
+    let data = add_section(&mut obj, ".data", SectionKind::Data, &[2, 0, 0x11, 0x22], 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let data_symbol = obj.section_symbol(data);
+    add_relocation(&mut obj, data, 0, data_symbol, kind, 16, 0);
+    obj
+}
+
+#[test]
+fn sixteen_bit_offset() {
+    let run = convert("sixteen_bit_offset", &[word_pointer(RelocationKind::Absolute)], &[]);
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(run.exe().fixups(), vec![Fixup { page: 2, source: 0, source_type: 0x05, target_object: 2, target_offset: 2 }]);
+}
+
+#[test]
+fn sixteen_bit_self_relative() {
+    let run = convert("sixteen_bit_self_relative", &[word_pointer(RelocationKind::Relative)], &[]);
+    assert!(!run.success);
+    assert!(run.stderr.contains(".data+0x0: unsupported relocation Relative of 16 bits"), "{}", run.stderr);
+}
+
+#[test]
+fn common_symbols_stay_aligned() {
+    let mut obj = new_object();
+    // mov eax, [buf]; ret
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xA1, 0, 0, 0, 0, 0xC3], 1);
+    // A byte of bss first, so COMMON doesn't start out aligned
+    let bss = obj.add_section(Vec::new(), Vec::from(".bss"), SectionKind::UninitializedData);
+    obj.append_section_bss(bss, 1, 1);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    add_symbol(&mut obj, "flag", bss, 0, SymbolKind::Data);
+    // The value of a COMMON symbol is its alignment
+    let buf = obj.add_symbol(Symbol {
+        name: Vec::from("buf"),
+        value: 16,
+        size: 32,
+        kind: SymbolKind::Data,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Common,
+        flags: SymbolFlags::None,
+    });
+    add_relocation(&mut obj, text, 1, buf, RelocationKind::Absolute, 32, 0);
+    let run = convert("common_symbols_stay_aligned", &[obj], &[]);
+    assert!(run.success, "{}", run.stderr);
+    let fixups = run.exe().fixups();
+    assert_eq!(fixups.len(), 1);
+    assert_eq!(fixups[0].target_object, 2);
+    assert_eq!(fixups[0].target_offset % 16, 0, "{:?}", fixups[0]);
+}
+
+#[test]
+fn gc_sections_without_text_start() {
+    let mut obj = new_object();
+    // The empty .text compilers emit comes first
+    add_section(&mut obj, ".text", SectionKind::Text, &[], 1);
+    // call used; ret
+    let main = add_section(&mut obj, ".text.main", SectionKind::Text, &[0xE8, 0, 0, 0, 0, 0xC3], 1);
+    add_section(&mut obj, ".text.unused", SectionKind::Text, &[0x90, 0xC3], 1);
+    let used = add_section(&mut obj, ".text.used", SectionKind::Text, &[0xC3], 1);
+    add_symbol(&mut obj, "main", main, 0, SymbolKind::Text);
+    let used_symbol = add_symbol(&mut obj, "used", used, 0, SymbolKind::Text);
+    add_relocation(&mut obj, main, 1, used_symbol, RelocationKind::Relative, 32, -4);
+    let run = convert("gc_sections_without_text_start", &[obj], &["--gc-sections"]);
+    assert!(run.success, "{}", run.stderr);
+    assert!(!run.stderr.contains("no code to run"), "{}", run.stderr);
+    // .text.used right after .text.main, .text.unused went
+    assert_eq!(run.exe().fixups(), vec![Fixup { page: 1, source: 1, source_type: 0x08, target_object: 1, target_offset: 6 }]);
+}
+
+#[test]
+fn plain_ctors_come_before_prioritized_ones() {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xC3, 0xC3], 1);
+    // Prioritized first in the input, to be sure it's the layout putting it last
+    let prioritized = add_section(&mut obj, ".ctors.00100", SectionKind::Data, &[0; 4], 4);
+    let plain = add_section(&mut obj, ".ctors", SectionKind::Data, &[0; 4], 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let plain_ctor = add_symbol(&mut obj, "plain_ctor", text, 0, SymbolKind::Text);
+    let prioritized_ctor = add_symbol(&mut obj, "prioritized_ctor", text, 1, SymbolKind::Text);
+    add_relocation(&mut obj, plain, 0, plain_ctor, RelocationKind::Absolute, 32, 0);
+    add_relocation(&mut obj, prioritized, 0, prioritized_ctor, RelocationKind::Absolute, 32, 0);
+    let run = convert("plain_ctors_come_before_prioritized_ones", &[obj], &[]);
+    assert!(run.success, "{}", run.stderr);
+    let mut fixups = run.exe().fixups();
+    fixups.sort_by_key(|fixup| fixup.source);
+    assert_eq!(fixups, vec![
+        Fixup { page: 2, source: 0, source_type: 0x07, target_object: 1, target_offset: 0 },
+        Fixup { page: 2, source: 4, source_type: 0x07, target_object: 1, target_offset: 1 },
+    ]);
+}
+
+#[test]
+fn debug_elf_entry_and_symbols() {
+    use object::{Object as _, ObjectSymbol as _};
+    let mut obj = new_object();
+    // nop; ret
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0x90, 0xC3], 1);
+    let data = add_section(&mut obj, ".data", SectionKind::Data, &[1, 2, 3, 4], 4);
+    add_symbol(&mut obj, "start", text, 1, SymbolKind::Text);
+    add_symbol(&mut obj, "value", data, 0, SymbolKind::Data);
+    obj.add_symbol(Symbol {
+        name: Vec::from("buf"),
+        value: 4,
+        size: 8,
+        kind: SymbolKind::Data,
+        scope: SymbolScope::Linkage,
+        weak: false,
+        section: SymbolSection::Common,
+        flags: SymbolFlags::None,
+    });
+    // Code second, starting at its second byte
+    let layout = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("code_second.toml");
+    std::fs::write(&layout, "start = { object = \"CODE\", offset = 1 }\nstack = { object = \"DATA\", offset = 0x1000 }\n\n\
+        [[object]]\nname = \"DATA\"\nmin_size = 0x1000\nkinds = [\"data\", \"bss\"]\n\n\
+        [[object]]\nname = \"CODE\"\ncode = true\nkinds = [\"text\"]\n").unwrap();
+    let run = convert("debug_elf_entry_and_symbols", &[obj], &["--layout", layout.to_str().unwrap(), "--debug-elf", "out.dbg", "--debug-base", "2=0x400000"]);
+    assert!(run.success, "{}", run.stderr);
+    let data = std::fs::read(run.dir.join("out.dbg")).unwrap();
+    let elf = object::File::parse(&*data).unwrap();
+    assert_eq!(elf.entry(), 0x400001);
+    let mut names: Vec<&str> = elf.symbols().filter_map(|sym| sym.name().ok()).filter(|name| !name.is_empty()).collect();
+    names.sort();
+    assert_eq!(names, ["buf", "start", "value"]);
+}
+
+/// `nop; ret` in `.text` with a DWARF 2 line table for `a.c` giving line 1
+/// to the nop and line 2 to the ret.
+fn code_with_lines() -> Object<'static> {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0x90, 0xC3], 1);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    // compile_unit, no children, DW_AT_name string, DW_AT_stmt_list data4
+    add_section(&mut obj, ".debug_abbrev", SectionKind::Debug, &[1, 0x11, 0, 0x03, 0x08, 0x10, 0x06, 0, 0, 0], 1);
+    let mut info = vec![0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 4, 1];
+    info.extend_from_slice(b"a.c\0");
+    info.extend_from_slice(&[0, 0, 0, 0]);
+    let unit_length = info.len() as u32 - 4;
+    info[..4].copy_from_slice(&unit_length.to_le_bytes());
+    add_section(&mut obj, ".debug_info", SectionKind::Debug, &info, 1);
+    // Version 2, min_inst_length 1, default_is_stmt, line_base -5,
+    // line_range 14, opcode_base 13, no directories, one file
+    let mut header = vec![1, 1, 0xFB, 14, 13, 0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1, 0];
+    header.extend_from_slice(b"a.c\0\0\0\0\0");
+    let mut line = vec![0, 0, 0, 0, 2, 0];
+    line.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    line.extend_from_slice(&header);
+    // DW_LNE_set_address .text, copy, advance_pc 1, advance_line 1, copy,
+    // advance_pc 1, DW_LNE_end_sequence
+    let address = line.len() as u64 + 3;
+    line.extend_from_slice(&[0, 5, 2, 0, 0, 0, 0, 1, 2, 1, 3, 1, 1, 2, 1, 0, 1, 1]);
+    let unit_length = line.len() as u32 - 4;
+    line[..4].copy_from_slice(&unit_length.to_le_bytes());
+    let debug_line = add_section(&mut obj, ".debug_line", SectionKind::Debug, &line, 1);
+    let text_symbol = obj.section_symbol(text);
+    add_relocation(&mut obj, debug_line, address, text_symbol, RelocationKind::Absolute, 32, 0);
+    obj
+}
+
+#[test]
+fn watcom_lines_in_the_code_object() {
+    // Code second
+    let layout = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("code_second_lines.toml");
+    std::fs::write(&layout, "start = { object = \"CODE\", offset = 0 }\nstack = { object = \"DATA\", offset = 0x1000 }\n\n\
+        [[object]]\nname = \"DATA\"\nmin_size = 0x1000\nkinds = [\"data\", \"bss\"]\n\n\
+        [[object]]\nname = \"CODE\"\ncode = true\nkinds = [\"text\"]\n").unwrap();
+    let run = convert("watcom_lines_in_the_code_object", &[code_with_lines()], &["--layout", layout.to_str().unwrap(), "--watcom-debug"]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    // The master header is the last 14 bytes, the section header follows
+    // the language and segment tables
+    let master = exe.0.len() - 14;
+    let u16_at = |offset: usize| u16::from_le_bytes([exe.0[offset], exe.0[offset + 1]]) as usize;
+    let debug_start = exe.0.len() - exe.u32_at(master + 10) as usize;
+    let section = debug_start + u16_at(master + 6) + u16_at(master + 8);
+    // The first line block comes right after the section header
+    let block = section + 18;
+    assert_eq!(exe.u32_at(block), 2, "line block segment");
+    assert_eq!(u16_at(block + 4), 2, "line count");
+    let lines: Vec<(usize, u32)> = (0..2).map(|idx| (u16_at(block + 6 + idx * 6), exe.u32_at(block + 8 + idx * 6))).collect();
+    assert_eq!(lines, [(1, 0), (2, 1)]);
+    // Address info: the data object is one range, the code object's ranges
+    // belong to modules
+    let addr = section + exe.u32_at(section + 8) as usize;
+    assert_eq!((exe.u32_at(addr), u16_at(addr + 4), u16_at(addr + 6)), (0, 1, 1));
+    assert_eq!(u16_at(addr + 12), 0, "data range module");
+    let code = addr + 14;
+    assert_eq!((exe.u32_at(code), u16_at(code + 4), u16_at(code + 6)), (0, 2, 1));
+    assert_eq!(exe.u32_at(code + 8), 2, "code range size");
+}
+
+fn undefined_symbol(obj: &mut Object, name: &str, weak: bool) -> SymbolId {
+    obj.add_symbol(Symbol {
+        name: Vec::from(name),
+        value: 0,
+        size: 0,
+        kind: SymbolKind::Unknown,
+        scope: SymbolScope::Linkage,
+        weak,
+        section: SymbolSection::Undefined,
+        flags: SymbolFlags::None,
+    })
+}
+
+#[test]
+fn unresolved_call() {
+    let mut obj = new_object();
+    // nop; call missing; ret
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0x90, 0xE8, 0, 0, 0, 0, 0xC3], 1);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let missing = undefined_symbol(&mut obj, "missing", false);
+    add_relocation(&mut obj, text, 2, missing, RelocationKind::Relative, 32, -4);
+    let run = convert("unresolved_call", &[obj], &[]);
+    assert!(!run.success);
+    assert!(run.stderr.contains("in0.o: .text+0x2: undefined reference to missing"), "{}", run.stderr);
+}
+
+#[test]
+fn weak_undefined_pointer_is_zero() {
+    let mut obj = new_object();
+    // mov eax, weak_fn + 2; ret
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xB8, 2, 0, 0, 0, 0xC3], 1);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let weak_fn = undefined_symbol(&mut obj, "weak_fn", true);
+    add_relocation(&mut obj, text, 1, weak_fn, RelocationKind::Absolute, 32, 0);
+    let run = convert("weak_undefined_pointer_is_zero", &[obj], &[]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    assert_eq!(exe.fixups(), vec![]);
+    assert_eq!(&exe.page(1)[..6], &[0xB8, 2, 0, 0, 0, 0xC3]);
+}
+
+#[test]
+fn weak_undefined_call() {
+    let mut obj = new_object();
+    // call weak_fn; ret
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xE8, 0, 0, 0, 0, 0xC3], 1);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let weak_fn = undefined_symbol(&mut obj, "weak_fn", true);
+    add_relocation(&mut obj, text, 1, weak_fn, RelocationKind::Relative, 32, -4);
+    let run = convert("weak_undefined_call", &[obj], &[]);
+    assert!(!run.success);
+    assert!(run.stderr.contains(".text+0x1: unsupported relocation Relative to undefined weak weak_fn"), "{}", run.stderr);
+}
+
+#[test]
+fn zero_pages_and_trailing_zeros() {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xC3], 1);
+    // Data pages: 1 not zero, 2 zero with a pointer at its end that reaches
+    // into 3, otherwise zero, 4 zero, 5 with one byte, then zeros to the end
+    let mut contents = vec![0u8; 0x6000];
+    contents[0] = 1;
+    contents[0x4010] = 1;
+    let data = add_section(&mut obj, ".data", SectionKind::Data, &contents, 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let text_symbol = obj.section_symbol(text);
+    add_relocation(&mut obj, data, 0x1FFE, text_symbol, RelocationKind::Absolute, 32, 0);
+    let run = convert("zero_pages_and_trailing_zeros", &[obj], &[]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    assert_eq!(exe.page_map(), [(1, 0), (2, 0), (3, 0), (4, 0), (0, 3), (5, 0)]);
+    // Only the 0x11 bytes of the last page that are used are stored
+    let header = exe.header();
+    assert_eq!(exe.u32_at(header + 0x2C), 0x11);
+    assert_eq!(exe.0.len(), exe.u32_at(header + 0x80) as usize + 4 * 0x1000 + 0x11);
+}
+
+#[test]
+fn debug_symbol_named_like_its_section() {
+    use object::{Object as _, ObjectSymbol as _};
+    let mut obj = new_object();
+    // nasm's `section CODE` with a `CODE:` label in it
+    let code = add_section(&mut obj, "CODE", SectionKind::Text, &[0x90, 0xC3], 1);
+    add_symbol(&mut obj, "start", code, 1, SymbolKind::Text);
+    obj.add_symbol(Symbol {
+        name: Vec::from("CODE"),
+        value: 0,
+        size: 0,
+        kind: SymbolKind::Text,
+        scope: SymbolScope::Compilation,
+        weak: false,
+        section: SymbolSection::Section(code),
+        flags: SymbolFlags::None,
+    });
+    let run = convert("debug_symbol_named_like_its_section", &[obj], &["--debug-elf", "out.dbg"]);
+    assert!(run.success, "{}", run.stderr);
+    let data = std::fs::read(run.dir.join("out.dbg")).unwrap();
+    let elf = object::File::parse(&*data).unwrap();
+    let mut names: Vec<&str> = elf.symbols().filter_map(|sym| sym.name().ok()).filter(|name| !name.is_empty()).collect();
+    names.sort();
+    assert_eq!(names, ["CODE", "start"]);
+}
+
+/// The 32-bit sum of little-endian dwords, the last one padded with zeros.
+fn dword_sum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut dword = [0u8; 4];
+        dword[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_le_bytes(dword))
+    })
+}
+
+#[test]
+fn checksums_and_verify() {
+    let mut obj = new_object();
+    // mov eax, [value]; ret
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xA1, 0, 0, 0, 0, 0xC3], 1);
+    let data = add_section(&mut obj, ".data", SectionKind::Data, &[1, 2, 3, 4, 5], 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    let value = add_symbol(&mut obj, "value", data, 0, SymbolKind::Data);
+    add_relocation(&mut obj, text, 1, value, RelocationKind::Absolute, 32, 0);
+    let run = convert("checksums_and_verify", &[obj], &["--checksums"]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    let header = exe.header();
+    let fixup_section = header + exe.u32_at(header + 0x68) as usize;
+    let fixup_checksum = dword_sum(&exe.0[fixup_section..fixup_section + exe.u32_at(header + 0x30) as usize]);
+    assert_ne!(fixup_checksum, 0);
+    assert_eq!(exe.u32_at(header + 0x34), fixup_checksum);
+    let loader_section = header + exe.u32_at(header + 0x40) as usize;
+    let loader_checksum = dword_sum(&exe.0[loader_section..loader_section + exe.u32_at(header + 0x38) as usize]);
+    assert_eq!(exe.u32_at(header + 0x3C), loader_checksum);
+    // Both pages are stored, the last one only as far as it's used
+    let page_checksums = header + exe.u32_at(header + 0x7C) as usize;
+    assert_eq!(exe.page_map(), [(1, 0), (2, 0)]);
+    assert_eq!(exe.u32_at(page_checksums), dword_sum(exe.page(1)));
+    assert_eq!(exe.u32_at(page_checksums + 4), dword_sum(&exe.page(2)[..5]));
+
+    let verify = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_elf2le")).current_dir(&run.dir).args(args).output().unwrap();
+    assert!(verify(&["--verify", "a.exe"]).status.success());
+    let mut flipped = exe.0.clone();
+    let last = flipped.len() - 1;
+    flipped[last] ^= 0x40;
+    std::fs::write(run.dir.join("flipped.exe"), flipped).unwrap();
+    let output = verify(&["--verify", "flipped.exe"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("page 2 checksum"), "{}", String::from_utf8_lossy(&output.stdout));
+}
+
+impl Le {
+    /// A names table at `offset` from the header: name, ordinal, up to the
+    /// zero length that ends it.
+    fn names(&self, offset: usize) -> Vec<(String, u16)> {
+        let mut pos = self.header() + offset;
+        let mut names = Vec::new();
+        while self.0[pos] != 0 {
+            let len = self.0[pos] as usize;
+            let name = String::from_utf8_lossy(&self.0[pos + 1..pos + 1 + len]).into_owned();
+            names.push((name, u16::from_le_bytes([self.0[pos + 1 + len], self.0[pos + 2 + len]])));
+            pos += len + 3;
+        }
+        names
+    }
+}
+
+#[test]
+fn dll_entry_table_skips_unused_ordinals() {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xC3; 16], 1);
+    let data = add_section(&mut obj, ".data", SectionKind::Data, &[0; 8], 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    add_symbol(&mut obj, "one", text, 1, SymbolKind::Text);
+    add_symbol(&mut obj, "two", text, 2, SymbolKind::Text);
+    add_symbol(&mut obj, "five", text, 5, SymbolKind::Text);
+    add_symbol(&mut obj, "table", data, 4, SymbolKind::Data);
+    let run = convert("dll_entry_table_skips_unused_ordinals", &[obj], &[
+        "--dll", "MYDLL", "--export", "one@1", "--export", "two@2", "--export", "five@5", "--export", "TABLE=table@300",
+    ]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    let header = exe.header();
+    let entry_table = header + exe.u32_at(header + 0x5C) as usize;
+    let expected = [
+        // Ordinals 1 and 2 in object 1: count, type 3, object, then flags and offset each
+        2, 0x03, 1, 0, 0x01, 1, 0, 0, 0, 0x01, 2, 0, 0, 0,
+        // 3 and 4 unused
+        2, 0x00,
+        1, 0x03, 1, 0, 0x01, 5, 0, 0, 0,
+        // 6 to 299 unused, more than one bundle can count
+        255, 0x00, 39, 0x00,
+        1, 0x03, 2, 0, 0x01, 4, 0, 0, 0,
+        0,
+    ];
+    assert_eq!(&exe.0[entry_table..entry_table + expected.len()], expected);
+    assert_eq!(exe.names(exe.u32_at(header + 0x58) as usize), [
+        (String::from("MYDLL"), 0), (String::from("one"), 1), (String::from("two"), 2), (String::from("five"), 5), (String::from("TABLE"), 300),
+    ]);
+}
+
+/// What an import fixup points at.
+#[derive(Debug, PartialEq)]
+enum Import {
+    Ordinal(String, u16),
+    Name(String, String),
+}
+
+impl Le {
+    /// Import fixups of page 1: source offset, flags and target, with the
+    /// module and procedure names looked up in their tables.
+    fn imports(&self) -> Vec<(i16, u8, Import)> {
+        let header = self.header();
+        let mut modules = Vec::new();
+        let mut pos = header + self.u32_at(header + 0x70) as usize;
+        for _ in 0..self.u32_at(header + 0x74) {
+            let len = self.0[pos] as usize;
+            modules.push(String::from_utf8_lossy(&self.0[pos + 1..pos + 1 + len]).into_owned());
+            pos += len + 1;
+        }
+        let procedures = header + self.u32_at(header + 0x78) as usize;
+        let u16_at = |pos: usize| u16::from_le_bytes([self.0[pos], self.0[pos + 1]]);
+        let page_table = header + self.u32_at(header + 0x68) as usize;
+        let records = header + self.u32_at(header + 0x6C) as usize;
+        let mut pos = records + self.u32_at(page_table) as usize;
+        let end = records + self.u32_at(page_table + 4) as usize;
+        let mut imports = Vec::new();
+        while pos < end {
+            let (source_type, flags) = (self.0[pos], self.0[pos + 1]);
+            assert_eq!(source_type & 0x20, 0, "imports aren't in source lists here");
+            let source = u16_at(pos + 2) as i16;
+            pos += 4;
+            // Module ordinal, 16-bit with flag 0x40
+            let module = if flags & 0x40 != 0 { pos += 2; u16_at(pos - 2) as usize } else { pos += 1; self.0[pos - 1] as usize };
+            let module = modules[module - 1].clone();
+            let target = match flags & 0x03 {
+                0x01 => { pos += 2; Import::Ordinal(module, u16_at(pos - 2)) },
+                0x02 => {
+                    let name_offset = if flags & 0x10 != 0 { pos += 4; self.u32_at(pos - 4) as usize } else { pos += 2; u16_at(pos - 2) as usize };
+                    let len = self.0[procedures + name_offset] as usize;
+                    Import::Name(module, String::from_utf8_lossy(&self.0[procedures + name_offset + 1..procedures + name_offset + 1 + len]).into_owned())
+                },
+                _ => panic!("fixup at 0x{:x} isn't an import", source)
+            };
+            assert_eq!(flags & 0x04, 0, "no additives here");
+            imports.push((source, flags, target));
+        }
+        imports
+    }
+}
+
+#[test]
+fn imports_by_ordinal_and_by_name() {
+    // 256 modules, so the last one needs a 16-bit module ordinal
+    let mut defs = String::from("IMPORTS\n");
+    for module in 0..255 {
+        defs += &format!("f{} = M{}.{}\n", module, module, module + 1);
+    }
+    defs += "f255 = M255.last\nhello = M0.hello\nbig = M255.7\n";
+    let names: Vec<String> = (0..256).map(|module| format!("f{}", module)).chain([String::from("hello"), String::from("big")]).collect();
+
+    let mut obj = new_object();
+    // A table of pointers to all of them
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &vec![0; names.len() * 4], 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    for (idx, name) in names.iter().enumerate() {
+        let symbol = undefined_symbol(&mut obj, name, false);
+        add_relocation(&mut obj, text, idx as u64 * 4, symbol, RelocationKind::Absolute, 32, 0);
+    }
+    let def_file = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("imports.def");
+    std::fs::write(&def_file, defs).unwrap();
+    let run = convert("imports_by_ordinal_and_by_name", &[obj], &["--imports", def_file.to_str().unwrap()]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    let header = exe.header();
+    assert_eq!(exe.u32_at(header + 0x74), 256);
+    let imports = exe.imports();
+    assert_eq!(imports.len(), names.len());
+    let find = |source: usize| imports.iter().find(|(at, _, _)| *at as usize == source).unwrap();
+    assert_eq!(find(0), &(0, 0x01, Import::Ordinal(String::from("M0"), 1)));
+    assert_eq!(find(254 * 4), &(254 * 4, 0x01, Import::Ordinal(String::from("M254"), 255)));
+    // Flag 0x40, 16-bit module ordinal
+    assert_eq!(find(255 * 4).1 & !0x10, 0x42);
+    assert_eq!(find(255 * 4).2, Import::Name(String::from("M255"), String::from("last")));
+    assert_eq!(find(256 * 4).1 & !0x10, 0x02);
+    assert_eq!(find(256 * 4).2, Import::Name(String::from("M0"), String::from("hello")));
+    assert_eq!(find(257 * 4), &(257 * 4, 0x41, Import::Ordinal(String::from("M255"), 7)));
+    // The procedure name table starts with an empty name, offsets can't be 0
+    let procedures = header + exe.u32_at(header + 0x78) as usize;
+    assert_eq!(exe.0[procedures], 0);
+}
+
+#[test]
+fn vxd_header_and_ddb() {
+    let mut obj = new_object();
+    let ltext = add_section(&mut obj, "_LTEXT", SectionKind::Text, &[0xC3; 4], 1);
+    let ldata = add_section(&mut obj, "_LDATA", SectionKind::Data, &[0; 0x50], 4);
+    let itext = add_section(&mut obj, "_ITEXT", SectionKind::Text, &[0xC3], 1);
+    add_symbol(&mut obj, "control_proc", ltext, 0, SymbolKind::Text);
+    add_symbol(&mut obj, "init", itext, 0, SymbolKind::Text);
+    add_symbol(&mut obj, "MYVXD_DDB", ldata, 0x10, SymbolKind::Data);
+    let run = convert("vxd_header_and_ddb", &[obj], &["--vxd", "MYVXD", "--vxd-id", "0x4321", "--vxd-ddk", "0x30A"]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    let header = exe.header();
+    // Target OS: Windows 386
+    assert_eq!(&exe.0[header + 0x0A..header + 0x0C], [4, 0]);
+    // Module type: VxD
+    assert_eq!(exe.u32_at(header + 0x10), 0x00038000);
+    // LCODE and ICODE
+    assert_eq!(exe.u32_at(header + 0x44), 2);
+    assert_eq!(&exe.0[header + 0xB8..header + 0xBC], [0x21, 0x43, 0x0A, 0x03]);
+    // The DDB is ordinal 1 in LCODE, after the 4 bytes of _LTEXT: exported, shared data
+    let entry_table = header + exe.u32_at(header + 0x5C) as usize;
+    assert_eq!(&exe.0[entry_table..entry_table + 10], [1, 0x03, 1, 0, 0x03, 4 + 0x10, 0, 0, 0, 0]);
+    assert_eq!(exe.names(exe.u32_at(header + 0x58) as usize), [(String::from("MYVXD"), 0), (String::from("MYVXD_DDB"), 1)]);
+}
+
+/// `ret` as the whole program.
+fn ret_only() -> Object<'static> {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0xC3], 1);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    obj
+}
+
+/// An MZ program whose header is `header_paragraphs` long, with `image` after it
+/// and `overlay` past the end of its load image.
+fn mz_program(header_paragraphs: u16, image: &[u8], overlay: &[u8]) -> Vec<u8> {
+    let mut mz = vec![0u8; header_paragraphs as usize * 16];
+    mz[0..2].copy_from_slice(b"MZ");
+    let image_len = mz.len() + image.len();
+    mz[0x02..0x04].copy_from_slice(&((image_len % 512) as u16).to_le_bytes());
+    mz[0x04..0x06].copy_from_slice(&(image_len.div_ceil(512) as u16).to_le_bytes());
+    mz[0x08..0x0A].copy_from_slice(&header_paragraphs.to_le_bytes());
+    mz[0x18..0x1A].copy_from_slice(&0x40u16.to_le_bytes());
+    mz.extend_from_slice(image);
+    mz.extend_from_slice(overlay);
+    mz
+}
+
+#[test]
+fn stub_points_at_the_le_header() {
+    let run = convert("stub_points_at_the_le_header", &[ret_only()], &["--stub-message", "needs DOS/32A"]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    let header = exe.header();
+    assert_eq!(header % 16, 0);
+    assert_eq!(&exe.0[header..header + 2], b"LE");
+    assert!(exe.0[..header].windows(16).any(|text| text == b"needs DOS/32A\r\n$"));
+
+    // A stub of our own, e_lfanew gets filled in
+    let stub = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("stub.exe");
+    std::fs::write(&stub, mz_program(4, &[0xCD, 0x20, 0x90], &[])).unwrap();
+    let run = convert("stub_points_at_the_le_header", &[ret_only()], &["--stub", stub.to_str().unwrap()]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    assert_eq!(exe.header(), 0x50);
+    assert_eq!(&exe.0[0x40..0x43], [0xCD, 0x20, 0x90]);
+    assert_eq!(&exe.0[0x50..0x52], b"LE");
+
+    // Its header ends before e_lfanew
+    std::fs::write(&stub, mz_program(2, &[0; 0x20], &[])).unwrap();
+    let run = convert("stub_points_at_the_le_header", &[ret_only()], &["--stub", stub.to_str().unwrap()]);
+    assert!(!run.success);
+    assert!(run.stderr.contains("stub's MZ header is too small to hold e_lfanew at 0x3C"), "{}", run.stderr);
+}
+
+#[test]
+fn bind_places_the_le_header_for_the_extender() {
+    // A 0x45 byte load image, then an overlay that isn't part of it
+    let extender = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("extender.exe");
+    std::fs::write(&extender, mz_program(4, &[0x90; 5], b"overlay")).unwrap();
+    // Right where the load image ends, or at the next paragraph for DOS4GW
+    for (name, header) in [("dos32a", 0x45), ("dos4gw", 0x50)] {
+        let run = convert("bind_places_the_le_header_for_the_extender", &[ret_only()], &["--extender", name, "--bind", extender.to_str().unwrap()]);
+        assert!(run.success, "{}", run.stderr);
+        let exe = run.exe();
+        assert_eq!(exe.header(), header, "{}", name);
+        assert_eq!(&exe.0[0x40..0x45], [0x90; 5]);
+        assert_eq!(&exe.0[header..header + 2], b"LE");
+        // The data pages offset is from the top of the file, stub included
+        assert_eq!(exe.page(1)[0], 0xC3);
+        assert_eq!(exe.u32_at(header + 0x80) as usize, exe.0.len() - 1);
+    }
+}
+
+#[test]
+fn last_page_bytes_and_file_length() {
+    let mut obj = new_object();
+    let text = add_section(&mut obj, ".text", SectionKind::Text, &[0x90; 0x1010], 1);
+    let data = add_section(&mut obj, ".data", SectionKind::Data, &[1, 2, 3], 4);
+    add_symbol(&mut obj, "start", text, 0, SymbolKind::Text);
+    add_symbol(&mut obj, "value", data, 0, SymbolKind::Data);
+    let run = convert("last_page_bytes_and_file_length", &[obj], &[]);
+    assert!(run.success, "{}", run.stderr);
+    let exe = run.exe();
+    let header = exe.header();
+    assert_eq!(exe.page_map(), [(1, 0), (2, 0), (3, 0)]);
+    // Only the 3 bytes of .data are in the last page, the code object's
+    // second page is still stored whole
+    assert_eq!(exe.u32_at(header + 0x2C), 3);
+    assert_eq!(exe.0.len(), exe.u32_at(header + 0x80) as usize + 2 * 0x1000 + 3);
+    assert_eq!(exe.page(3), [1, 2, 3]);
+}
+
+#[test]
+fn missing_ddb_and_debug_base_object() {
+    let run = convert("missing_ddb_and_debug_base_object", &[ret_only()], &["--vxd", "MYVXD"]);
+    assert!(!run.success);
+    assert!(run.stderr.contains("symbol MYVXD_DDB isn't defined"), "{}", run.stderr);
+    let run = convert("missing_ddb_and_debug_base_object", &[ret_only()], &["--debug-elf", "a.dbg", "--debug-base", "3=0x10000"]);
+    assert!(!run.success);
+    assert!(run.stderr.contains("--debug-base: there's no LE object 3"), "{}", run.stderr);
+}